@@ -0,0 +1,303 @@
+//! A tokio flavour of `main_loop`, behind the `async` feature: every message
+//! is handled in its own task, so handlers can simply `await` replies to
+//! their own requests, kv operations and timers.
+
+use crate::handshake::Handshake;
+use crate::{
+    decode, parse_reply, strict_from_env, Body, Decoded, Envelope, Init, InitError, InitPayload,
+    Message, NodeId, Origin, Outbox, RpcError,
+};
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+
+pub trait AsyncNode<S, Payload>: Sized + 'static {
+    fn from_init(state: S, init: Init, handle: &Handle) -> anyhow::Result<Self>;
+
+    /// Handles one message. Many may be in flight at once, so state the
+    /// node changes across an `await` needs a `RefCell` or the like.
+    fn handle(
+        self: Rc<Self>,
+        input: Message<Payload>,
+        handle: Handle,
+    ) -> impl Future<Output = anyhow::Result<()>> + 'static;
+
+    /// Started once right after `init`, for background work like gossip.
+    fn run(self: Rc<Self>, handle: Handle) -> impl Future<Output = anyhow::Result<()>> + 'static {
+        let _ = handle;
+        async { Ok(()) }
+    }
+}
+
+struct Shared {
+    node_id: NodeId,
+    node_ids: Vec<NodeId>,
+    outbox: RefCell<Outbox>,
+    pending: RefCell<HashMap<usize, oneshot::Sender<Message<Value>>>>,
+    fatal: mpsc::UnboundedSender<anyhow::Error>,
+}
+
+/// What handlers use to talk to the world. Clones refer to the same node.
+#[derive(Clone)]
+pub struct Handle {
+    shared: Rc<Shared>,
+}
+
+impl Handle {
+    pub fn node_id(&self) -> NodeId {
+        self.shared.node_id
+    }
+
+    pub fn node_ids(&self) -> &[NodeId] {
+        &self.shared.node_ids
+    }
+
+    /// Sends `msg` right away under a fresh `msg_id`, which is returned.
+    pub fn send<P: Serialize>(&self, msg: &Message<P>) -> anyhow::Result<usize> {
+        let mut outbox = self.shared.outbox.borrow_mut();
+        let id = outbox.send(msg)?;
+        flush(&mut outbox)?;
+        Ok(id)
+    }
+
+    pub fn reply<Q, P: Serialize>(
+        &self,
+        request: &Message<Q>,
+        payload: P,
+    ) -> anyhow::Result<usize> {
+        self.send(&Message {
+            src: request.dst,
+            dst: request.src,
+            body: Body {
+                id: None,
+                in_reply_to: request.body.id,
+                payload,
+            },
+        })
+    }
+
+    /// Sends `request` to `dst` and waits up to `timeout` for the reply.
+    pub async fn call<Req, Resp>(
+        &self,
+        dst: NodeId,
+        request: Req,
+        timeout: Duration,
+    ) -> Result<Message<Resp>, RpcError>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        let msg = Message {
+            src: self.shared.node_id,
+            dst,
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                payload: serde_json::to_value(request).map_err(RpcError::Malformed)?,
+            },
+        };
+        let (tx, rx) = oneshot::channel();
+        let id = match self.send(&msg) {
+            Ok(id) => id,
+            Err(e) => {
+                // Output is gone, and the node with it; the call is as good
+                // as lost until then.
+                let _ = self.shared.fatal.send(e);
+                return Err(RpcError::Timeout);
+            }
+        };
+        self.shared.pending.borrow_mut().insert(id, tx);
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(reply)) => parse_reply(reply),
+            _ => {
+                self.shared.pending.borrow_mut().remove(&id);
+                Err(RpcError::Timeout)
+            }
+        }
+    }
+
+    pub async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+
+    /// Runs `task` alongside the handlers. If it fails, so does the node.
+    pub fn spawn(&self, task: impl Future<Output = anyhow::Result<()>> + 'static) {
+        let fatal = self.shared.fatal.clone();
+        tokio::task::spawn_local(async move {
+            if let Err(e) = task.await {
+                let _ = fatal.send(e);
+            }
+        });
+    }
+
+    pub fn log(&self, line: impl Into<String>) {
+        eprintln!("{}", line.into());
+    }
+
+    /// `line` as a whole, if it is a reply some call waits for.
+    fn awaited(&self, line: &str) -> Option<Message<Value>> {
+        let envelope = Envelope::parse(line).ok()?;
+        let id = envelope.in_reply_to?;
+        if !self.shared.pending.borrow().contains_key(&id) {
+            return None;
+        }
+        envelope.to_value().ok()
+    }
+
+    /// Hands `reply` to the call waiting on it, or gives it back.
+    fn resolve(&self, reply: Message<Value>) -> Result<(), Message<Value>> {
+        let Some(id) = reply.body.in_reply_to else {
+            return Err(reply);
+        };
+        let Some(tx) = self.shared.pending.borrow_mut().remove(&id) else {
+            return Err(reply);
+        };
+        // The caller may have timed out in the meantime.
+        let _ = tx.send(reply);
+        Ok(())
+    }
+}
+
+/// Writes out what `outbox` collected.
+fn flush(outbox: &mut Outbox) -> anyhow::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    for effect in outbox.take_effects() {
+        match effect {
+            crate::Effect::Send(msg) => msg.send(&mut stdout).context("send message")?,
+            crate::Effect::Log(line) => eprintln!("{}", line),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Runs `N` against Maelstrom over stdin and stdout on a single-threaded
+/// tokio runtime, with the same handshake and decoding as `main_loop`.
+pub fn async_main_loop<S, N, P>(init_state: S) -> anyhow::Result<()>
+where
+    N: AsyncNode<S, P>,
+    P: DeserializeOwned + Send + 'static,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("start tokio runtime")?;
+    tokio::task::LocalSet::new().block_on(&runtime, run::<S, N, P>(init_state))
+}
+
+async fn run<S, N, P>(init_state: S) -> anyhow::Result<()>
+where
+    N: AsyncNode<S, P>,
+    P: DeserializeOwned + Send + 'static,
+{
+    let strict = strict_from_env();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    let mut outbox = Outbox::new();
+    let mut handshake = Handshake::default();
+    let init_msg = loop {
+        let line = lines
+            .next_line()
+            .await
+            .context("failed to read init message from stdin")?
+            .ok_or(InitError::Eof)?;
+        let offered = handshake.offer(line, &mut outbox, strict);
+        flush(&mut outbox)?;
+        if let Some(init_msg) = offered.context("init handshake failed")? {
+            break init_msg;
+        }
+    };
+
+    let (fatal, mut failed) = mpsc::unbounded_channel();
+    let handle = Handle {
+        shared: Rc::new(Shared {
+            node_id: init_msg.body.payload.node_id,
+            node_ids: init_msg.body.payload.node_ids.clone(),
+            outbox: RefCell::new(outbox),
+            pending: RefCell::new(HashMap::new()),
+            fatal,
+        }),
+    };
+    let node = Rc::new(
+        N::from_init(init_state, init_msg.body.payload.clone(), &handle)
+            .context("node initilization failed")?,
+    );
+    handle
+        .reply(&init_msg, InitPayload::InitOk)
+        .context("serialize response to init")?;
+    handle.spawn(Rc::clone(&node).run(handle.clone()));
+
+    let mut handlers = JoinSet::new();
+    let mut early = handshake.into_early().into_iter();
+    loop {
+        while handlers.try_join_next().is_some() {}
+        let line = match early.next() {
+            Some(line) => line,
+            None => tokio::select! {
+                line = lines.next_line() => match line.context("Maelstrom input from STDIN could not be read")? {
+                    Some(line) => line,
+                    None => break,
+                },
+                Some(e) = failed.recv() => return Err(e).context("Node task failed"),
+            },
+        };
+        if let Some(reply) = handle.awaited(&line) {
+            if handle.resolve(reply).is_ok() {
+                continue;
+            }
+        }
+        let input = match decode(&line, None::<&crate::Rpc<P, ()>>, strict)? {
+            Decoded::Message(input) => input,
+            Decoded::Resolved => continue,
+            Decoded::Dropped(reason) => {
+                handle.log(reason);
+                continue;
+            }
+            Decoded::Rejected(reply, reason) => {
+                handle.log(reason);
+                handle.send(&reply)?;
+                continue;
+            }
+        };
+        let origin = Origin::of_message(&input);
+        let node = Rc::clone(&node);
+        let h = handle.clone();
+        handlers.spawn_local(async move {
+            let Err(e) = node.handle(input, h.clone()).await else {
+                return;
+            };
+            let Some(reply) = origin.error_reply(&e) else {
+                let _ = h.shared.fatal.send(e.context("Node handler failed"));
+                return;
+            };
+            h.log(format!("replying with error to {}: {:#}", reply.dst, e));
+            if let Err(e) = h.send(&reply) {
+                let _ = h.shared.fatal.send(e);
+            }
+        });
+    }
+
+    // Returning drops the handlers still running. No more replies can come
+    // in, so those waiting on one finish once their calls time out.
+    loop {
+        tokio::select! {
+            handled = handlers.join_next() => if handled.is_none() {
+                break;
+            },
+            Some(e) = failed.recv() => return Err(e).context("Node task failed"),
+        }
+    }
+    match failed.try_recv() {
+        Ok(e) => Err(e).context("Node task failed"),
+        Err(_) => Ok(()),
+    }
+}
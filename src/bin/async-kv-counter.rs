@@ -0,0 +1,72 @@
+//! The kv counter as an async node: each request awaits its kv calls in
+//! line rather than going through injected events. `lin-kv` reads are
+//! linearizable, so reads need no barrier.
+
+use rustengan::kv::{AsyncKv, KvError, LinKvService};
+use rustengan::kv_counter::Payload;
+use rustengan::*;
+use std::rc::Rc;
+
+/// The one key the counter is stored under in `lin-kv`.
+const COUNTER: &str = "counter";
+
+struct AsyncCounterNode {
+    kv: AsyncKv<LinKvService>,
+}
+
+impl AsyncCounterNode {
+    async fn read(&self) -> anyhow::Result<u64> {
+        match self.kv.read(COUNTER).await {
+            Ok(value) => Ok(value),
+            Err(KvError::KeyDoesNotExist) => Ok(0),
+            Err(e) => Err(ErrorBody::new(
+                ErrorCode::TemporarilyUnavailable,
+                format!("read: {}", e),
+            )
+            .into()),
+        }
+    }
+
+    async fn add(&self, delta: u64) -> anyhow::Result<()> {
+        loop {
+            let current = self.read().await?;
+            match self.kv.cas(COUNTER, current, current + delta, true).await {
+                Ok(()) => return Ok(()),
+                // Someone else's add got in between.
+                Err(KvError::PreconditionFailed) => continue,
+                // The swap may or may not have happened, so retrying could
+                // count `delta` twice.
+                Err(e) => {
+                    return Err(ErrorBody::new(ErrorCode::Timeout, format!("add: {}", e)).into())
+                }
+            }
+        }
+    }
+}
+
+impl AsyncNode<(), Payload> for AsyncCounterNode {
+    fn from_init(_state: (), _init: Init, handle: &Handle) -> anyhow::Result<Self> {
+        Ok(Self {
+            kv: AsyncKv::new(handle.clone()),
+        })
+    }
+
+    async fn handle(self: Rc<Self>, input: Message<Payload>, handle: Handle) -> anyhow::Result<()> {
+        match input.body.payload {
+            Payload::Add { delta } => {
+                self.add(delta).await?;
+                handle.reply(&input, Payload::AddOk)?;
+            }
+            Payload::Read => {
+                let value = self.read().await?;
+                handle.reply(&input, Payload::ReadOk { value })?;
+            }
+            Payload::AddOk | Payload::ReadOk { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    async_main_loop::<_, AsyncCounterNode, _>(())
+}
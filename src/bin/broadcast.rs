@@ -1,11 +1,12 @@
+use rustengan::crdt::{GSet, Sync};
+use rustengan::gossip::{Gossip, Neighbors};
+use rustengan::middleware::{CatchPanicLayer, DedupLayer, Layers};
 use rustengan::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
-use std::io::StdoutLock;
 use std::sync::mpsc::Sender;
-use std::thread;
 use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,122 +19,78 @@ enum Payload {
     BroadcastOk,
     Read,
     ReadOk {
-        messages: HashSet<usize>,
+        messages: GSet<usize>,
     },
     Topology {
-        topology: HashMap<String, Vec<String>>,
+        topology: HashMap<NodeId, Vec<NodeId>>,
     },
     TopologyOk,
-    Gossip {
-        seen: HashSet<usize>,
-    },
+    Gossip(Sync<GSet<usize>>),
 }
 
+#[derive(Clone)]
 enum InjectedPayload {
     Gossip,
 }
 struct BroadcastNode {
-    node: String,
-    id: usize,
-    messages: HashSet<usize>,
-    neighborhood: Vec<String>,
-    known: HashMap<String, HashSet<usize>>,
+    node: NodeId,
+    messages: Gossip<GSet<usize>, Payload, Neighbors>,
 }
 
 impl Node<(), Payload, InjectedPayload> for BroadcastNode {
     fn from_init(
         _state: (),
         init: Init,
-        tx: Sender<Event<Payload, InjectedPayload>>,
+        _tx: Sender<Event<Payload, InjectedPayload>>,
     ) -> anyhow::Result<Self> {
-        thread::spawn(move || loop {
-            thread::sleep(Duration::from_millis(300));
-            if let Err(_) = tx.send(Event::Injected(InjectedPayload::Gossip)) {
-                break;
-            }
-        });
+        let cluster = Cluster::new(&init)?;
         Ok(Self {
             node: init.node_id,
-            id: 1,
-            messages: HashSet::new(),
-            known: init
-                .node_ids
-                .into_iter()
-                .map(|nid| (nid, HashSet::new()))
-                .collect(),
-            neighborhood: Vec::new(),
+            messages: Gossip::new(&cluster, GSet::new(), Neighbors::default(), Payload::Gossip),
         })
     }
 
     fn step(
         &mut self,
         input: Event<Payload, InjectedPayload>,
-        output: &mut StdoutLock,
+        outbox: &mut Outbox<InjectedPayload>,
     ) -> anyhow::Result<()> {
         match input {
             Event::EOF => {}
-            Event::Injected(payload) => match payload {
-                InjectedPayload::Gossip => {
-                    for n in &self.neighborhood {
-                        let knows_to_n = &self.known[n];
-                        Message {
-                            src: self.node.clone(),
-                            dst: n.clone(),
-                            body: Body {
-                                id: None,
-                                in_reply_to: None,
-                                payload: Payload::Gossip {
-                                    seen: self
-                                        .messages
-                                        .iter()
-                                        .copied()
-                                        .filter(|m| !knows_to_n.contains(m))
-                                        .collect(),
-                                },
-                            },
-                        }
-                        .send(output)
-                        .with_context(|| format!("gossip to {}", n))?;
-                        self.id += 1;
-                    }
-                }
-            },
+            Event::Injected(InjectedPayload::Gossip) => self.messages.round(outbox)?,
             Event::Message(input) => {
-                let mut reply = input.into_reply(Some(&mut self.id));
+                let mut reply = input.into_reply();
                 match reply.body.payload {
-                    Payload::Gossip { seen } => {
-                        self.known
-                            .get_mut(&reply.dst)
-                            .expect("get from unknow node")
-                            .extend(seen.iter().copied());
-                        self.messages.extend(seen);
+                    Payload::Gossip(sync) => {
+                        self.messages.receive(reply.dst, &sync);
                     }
                     Payload::Broadcast { message } => {
-                        self.messages.insert(message);
+                        self.messages.state_mut().insert(message);
                         reply.body.payload = Payload::BroadcastOk;
-                        reply
-                            .send(output)
+                        outbox
+                            .send(&reply)
                             .context("serialize response to generate")?;
-                        self.id += 1;
                     }
                     Payload::Read => {
                         reply.body.payload = Payload::ReadOk {
-                            messages: self.messages.clone(),
+                            messages: self.messages.state().clone(),
                         };
-                        reply
-                            .send(output)
+                        outbox
+                            .send(&reply)
                             .context("serialize response to generate")?;
-                        self.id += 1;
                     }
                     Payload::Topology { mut topology } => {
-                        self.neighborhood = topology
-                            .remove(&self.node)
-                            .unwrap_or_else(|| panic!("not topology give for node {}", self.node));
+                        let neighborhood = topology.remove(&self.node).ok_or_else(|| {
+                            ErrorBody::new(
+                                ErrorCode::MalformedRequest,
+                                format!("not topology give for node {}", self.node),
+                            )
+                        })?;
+                        *self.messages.selection_mut() = Neighbors(neighborhood);
                         reply.body.payload = Payload::TopologyOk;
-                        reply
-                            .send(output)
+                        outbox
+                            .send(&reply)
                             .context("serialize response to generate")?;
-                        self.id += 1;
                     }
                     Payload::ReadOk { messages: _ }
                     | Payload::BroadcastOk
@@ -143,8 +100,17 @@ impl Node<(), Payload, InjectedPayload> for BroadcastNode {
         }
         Ok(())
     }
+
+    fn periodic(&self) -> Vec<(Duration, InjectedPayload)> {
+        vec![(self.messages.interval(), InjectedPayload::Gossip)]
+    }
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<_, BroadcastNode, _, _>(())
+    // Gossip is idempotent anyway, but duplicated messages need not be
+    // handled twice.
+    let layers = Layers::new()
+        .layer(CatchPanicLayer)
+        .layer(DedupLayer::default());
+    main_loop_with::<_, BroadcastNode, _, _, _>((), layers)
 }
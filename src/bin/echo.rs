@@ -1,50 +1,29 @@
 use rustengan::*;
 
-use anyhow::Context;
 use serde::{Deserialize, Serialize};
-use std::io::{StdoutLock, Write};
 use std::sync::mpsc::Sender;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
-enum Payload {
-    Echo { echo: String },
-    EchoOk { echo: String },
+struct Echo {
+    echo: String,
 }
 
-struct EchoNode {
-    id: usize,
-}
+struct EchoNode;
 
-impl Node<(), Payload> for EchoNode {
+impl Routes<()> for EchoNode {
     fn from_init(
         _state: (),
         _init: rustengan::Init,
-        _tx: Sender<Event<Payload>>,
+        _tx: Sender<Event<RawPayload>>,
     ) -> anyhow::Result<Self> {
-        Ok(EchoNode { id: 1 })
+        Ok(EchoNode)
     }
 
-    fn step(&mut self, input: Event<Payload>, output: &mut StdoutLock) -> anyhow::Result<()> {
-        let Event::Message(input) = input else {
-            panic!("err");
-        };
-        let mut reply = input.into_reply(Some(&mut self.id));
-        match reply.body.payload {
-            Payload::Echo { echo } => {
-                reply.body.payload = Payload::EchoOk { echo };
-                serde_json::to_writer(&mut *output, &reply)
-                    .context("serialize response to init")?;
-                output.write_all(b"\n").context("write trailing newline")?;
-                self.id += 1;
-            }
-            Payload::EchoOk { .. } => {}
-        }
-        Ok(())
+    fn router() -> Router<Self> {
+        Router::new().handle("echo", |_: &mut Self, echo: Echo| Ok(echo))
     }
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<_, EchoNode, _, _>(())
+    main_loop::<_, Routed<EchoNode>, _, _>(())
 }
@@ -0,0 +1,6 @@
+use rustengan::kv_counter::KvCounterNode;
+use rustengan::*;
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, KvCounterNode, _, _>(())
+}
@@ -0,0 +1,7 @@
+use rustengan::counter::CounterNode;
+use rustengan::crdt::PNCounter;
+use rustengan::*;
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, CounterNode<PNCounter>, _, _>(())
+}
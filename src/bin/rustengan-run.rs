@@ -0,0 +1,540 @@
+use anyhow::Context;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::faults::{Faults, Network, PartitionKind, PartitionSchedule};
+use rustengan::services::Services;
+use rustengan::workload::{self, Clients, Workload};
+use rustengan::*;
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{BufRead, BufReader, LineWriter, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: rustengan-run <echo|unique-ids|broadcast|g-counter|pn-counter|kafka> \
+[--bin PATH] [--node-count N] [--time-limit SECS] [--rate OPS_PER_SEC] \
+[--concurrency CLIENTS] [--recovery SECS] [--seed SEED] [--drop P] [--link-drop SRC:DST:P] \
+[--duplicate P] [--delay none|uniform:MIN_MS:MAX_MS|exp:MEAN_MS] \
+[--partition halves|majority|bridge] [--partition-interval SECS] \
+[--transport stdio|tcp|unix] [--strict]";
+
+struct Args {
+    workload: String,
+    bin: PathBuf,
+    node_count: usize,
+    time_limit: Duration,
+    rate: f64,
+    concurrency: usize,
+    recovery: Duration,
+    seed: u64,
+    faults: Faults,
+    /// Nodes exit on input they cannot decode instead of skipping it.
+    strict: bool,
+    /// How the nodes are connected to the runner.
+    transport: Link,
+}
+
+/// How the runner talks to its nodes: over their stdin and stdout as
+/// Maelstrom does, or over sockets the nodes connect to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Link {
+    Stdio,
+    Tcp,
+    Unix,
+}
+
+impl std::str::FromStr for Link {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "stdio" => Ok(Link::Stdio),
+            "tcp" => Ok(Link::Tcp),
+            "unix" if cfg!(unix) => Ok(Link::Unix),
+            _ => anyhow::bail!("unknown transport {}", s),
+        }
+    }
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = std::env::args().skip(1);
+    let workload = args.next().context(USAGE)?;
+    let mut bin = None;
+    let mut node_count = None;
+    let mut parsed = Args {
+        workload,
+        bin: PathBuf::new(),
+        node_count: 3,
+        time_limit: Duration::from_secs(5),
+        rate: 50.0,
+        concurrency: 4,
+        recovery: Duration::from_secs(2),
+        seed: 0,
+        faults: Faults::default(),
+        strict: false,
+        transport: Link::Stdio,
+    };
+    let mut partition = None;
+    let mut partition_interval = Duration::from_secs(1);
+    while let Some(flag) = args.next() {
+        if flag == "--strict" {
+            parsed.strict = true;
+            continue;
+        }
+        let value = args
+            .next()
+            .with_context(|| format!("{} needs a value\n{}", flag, USAGE))?;
+        match flag.as_str() {
+            "--bin" => bin = Some(PathBuf::from(value)),
+            "--node-count" => node_count = Some(value.parse()?),
+            "--time-limit" => parsed.time_limit = Duration::from_secs_f64(value.parse()?),
+            "--rate" => parsed.rate = value.parse()?,
+            "--concurrency" => parsed.concurrency = value.parse()?,
+            "--recovery" => parsed.recovery = Duration::from_secs_f64(value.parse()?),
+            "--seed" => parsed.seed = value.parse()?,
+            "--drop" => parsed.faults.drop = value.parse()?,
+            "--link-drop" => {
+                let [src, dst, p] = value.split(':').collect::<Vec<_>>()[..] else {
+                    anyhow::bail!("bad link drop {}, expected SRC:DST:P", value);
+                };
+                parsed
+                    .faults
+                    .link_drop
+                    .insert((NodeId::from(src), NodeId::from(dst)), p.parse()?);
+            }
+            "--duplicate" => parsed.faults.duplicate = value.parse()?,
+            "--delay" => parsed.faults.delay = value.parse()?,
+            "--partition" => partition = Some(value.parse::<PartitionKind>()?),
+            "--partition-interval" => partition_interval = Duration::from_secs_f64(value.parse()?),
+            "--transport" => parsed.transport = value.parse()?,
+            _ => anyhow::bail!("unknown flag {}\n{}", flag, USAGE),
+        }
+    }
+    parsed.faults.partition = partition.map(|kind| PartitionSchedule {
+        kind,
+        interval: partition_interval,
+    });
+    parsed.bin = match bin {
+        Some(bin) => {
+            parsed.node_count = node_count.unwrap_or(parsed.node_count);
+            bin
+        }
+        None => {
+            let name = workload::default_bin(&parsed.workload)
+                .with_context(|| format!("unknown workload {}\n{}", parsed.workload, USAGE))?;
+            let max = workload::default_bin_max_nodes(&parsed.workload);
+            parsed.node_count = match (node_count, max) {
+                (Some(n), Some(max)) if n > max => anyhow::bail!(
+                    "{} works on at most {} node(s), not {}; pass --bin for one that works on more",
+                    name,
+                    max,
+                    n
+                ),
+                (Some(n), _) => n,
+                (None, max) => max.map_or(parsed.node_count, |max| max.min(parsed.node_count)),
+            };
+            std::env::current_exe()
+                .context("locate rustengan-run")?
+                .with_file_name(name)
+        }
+    };
+    Ok(parsed)
+}
+
+struct Cluster {
+    nodes: Vec<NodeId>,
+    inputs: HashMap<NodeId, Box<dyn Write>>,
+    children: Vec<Child>,
+    services: Services,
+    network: Network,
+    start: Instant,
+    delayed: BinaryHeap<Reverse<(Instant, u64, String)>>,
+    seq: u64,
+    delivered: usize,
+    listener: Listener,
+}
+
+/// Where nodes connect to when not talking over stdio.
+enum Listener {
+    Stdio,
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    fn bind(link: Link) -> anyhow::Result<Self> {
+        match link {
+            Link::Stdio => Ok(Listener::Stdio),
+            Link::Tcp => Ok(Listener::Tcp(
+                TcpListener::bind("127.0.0.1:0").context("bind tcp listener")?,
+            )),
+            #[cfg(unix)]
+            Link::Unix => {
+                let path =
+                    std::env::temp_dir().join(format!("rustengan-run-{}.sock", std::process::id()));
+                let _ = std::fs::remove_file(&path);
+                let listener = UnixListener::bind(&path)
+                    .with_context(|| format!("bind {}", path.display()))?;
+                Ok(Listener::Unix(listener, path))
+            }
+            #[cfg(not(unix))]
+            Link::Unix => anyhow::bail!("unix sockets are not supported here"),
+        }
+    }
+
+    /// What to set `RUSTENGAN_TRANSPORT` to for nodes to connect here.
+    fn spec(&self) -> anyhow::Result<String> {
+        match self {
+            Listener::Stdio => Ok("stdio".to_string()),
+            Listener::Tcp(listener) => Ok(format!("tcp:{}", listener.local_addr()?)),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(format!("unix:{}", path.display())),
+        }
+    }
+
+    /// Waits for `child`, just spawned, to connect. Nodes are spawned one at
+    /// a time, so the next connection is theirs.
+    fn accept(&self, child: &mut Child) -> anyhow::Result<(Box<dyn Read + Send>, Box<dyn Write>)> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let accepted: std::io::Result<(Box<dyn Read + Send>, Box<dyn Write>)> = match self {
+                Listener::Stdio => unreachable!("stdio nodes do not connect"),
+                Listener::Tcp(listener) => {
+                    listener.set_nonblocking(true)?;
+                    listener.accept().and_then(|(stream, _)| {
+                        stream.set_nonblocking(false)?;
+                        stream.set_nodelay(true)?;
+                        Ok((
+                            Box::new(stream.try_clone()?) as Box<dyn Read + Send>,
+                            Box::new(LineWriter::new(stream)) as Box<dyn Write>,
+                        ))
+                    })
+                }
+                #[cfg(unix)]
+                Listener::Unix(listener, _) => {
+                    listener.set_nonblocking(true)?;
+                    listener.accept().and_then(|(stream, _)| {
+                        stream.set_nonblocking(false)?;
+                        Ok((
+                            Box::new(stream.try_clone()?) as Box<dyn Read + Send>,
+                            Box::new(LineWriter::new(stream)) as Box<dyn Write>,
+                        ))
+                    })
+                }
+            };
+            match accepted {
+                Ok(streams) => return Ok(streams),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e).context("accept connection"),
+            }
+            if let Some(status) = child.try_wait()? {
+                anyhow::bail!("exited with {} before connecting", status);
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("never connected");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Cluster {
+    fn spawn(args: &Args) -> anyhow::Result<(Self, Receiver<String>)> {
+        let (tx, rx) = channel();
+        let mut cluster = Cluster {
+            nodes: Vec::new(),
+            inputs: HashMap::new(),
+            children: Vec::new(),
+            services: Services::new(args.seed),
+            network: Network::new(args.faults.clone(), args.seed),
+            start: Instant::now(),
+            delayed: BinaryHeap::new(),
+            seq: 0,
+            delivered: 0,
+            listener: Listener::Stdio,
+        };
+        let listener = Listener::bind(args.transport)?;
+        for i in 0..args.node_count {
+            let node = NodeId::server(i as u32);
+            let mut command = Command::new(&args.bin);
+            command
+                .env("RUSTENGAN_STRICT", if args.strict { "1" } else { "0" })
+                .stderr(Stdio::inherit());
+            match &listener {
+                Listener::Stdio => command.stdin(Stdio::piped()).stdout(Stdio::piped()),
+                listener => command
+                    .env("RUSTENGAN_TRANSPORT", listener.spec()?)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null()),
+            };
+            let mut child = command
+                .spawn()
+                .with_context(|| format!("spawn {} as {}", args.bin.display(), node))?;
+            let (output, input): (Box<dyn Read + Send>, Box<dyn Write>) = match &listener {
+                Listener::Stdio => (
+                    Box::new(child.stdout.take().expect("stdout is piped")),
+                    Box::new(child.stdin.take().expect("stdin is piped")),
+                ),
+                listener => listener
+                    .accept(&mut child)
+                    .with_context(|| format!("wait for {} to connect", node))?,
+            };
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(output).lines() {
+                    let Ok(line) = line else { break };
+                    if tx.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+            cluster.inputs.insert(node, input);
+            cluster.children.push(child);
+            cluster.nodes.push(node);
+        }
+        cluster.listener = listener;
+        Ok((cluster, rx))
+    }
+
+    fn deliver(&mut self, msg: &Message<Value>) -> anyhow::Result<()> {
+        let input = self
+            .inputs
+            .get_mut(&msg.dst)
+            .with_context(|| format!("no node {}", msg.dst))?;
+        msg.send(input)
+            .with_context(|| format!("deliver to {}", msg.dst))?;
+        self.delivered += 1;
+        Ok(())
+    }
+
+    /// Sends a line emitted by a node on to its destination, answering on
+    /// behalf of the kv/tso services and subjecting traffic between nodes to
+    /// the configured faults. Messages for clients are returned.
+    fn route(&mut self, line: &str) -> anyhow::Result<Option<Message<Value>>> {
+        let msg: Message<Value> =
+            serde_json::from_str(line).with_context(|| format!("node emitted {}", line))?;
+        if msg.dst.is_client() {
+            return Ok(Some(msg));
+        }
+        if self.services.contains(msg.dst) {
+            let reply = self.services.handle(msg).expect("service exists");
+            self.deliver(&reply)?;
+            return Ok(None);
+        }
+        if let Some(change) = self.network.advance(self.start.elapsed(), &self.nodes) {
+            eprintln!("{:?}: {}", self.start.elapsed(), change);
+        }
+        for delay in self.network.deliveries(msg.src, msg.dst) {
+            if delay.is_zero() {
+                self.deliver(&msg)?;
+            } else {
+                self.seq += 1;
+                self.delayed.push(Reverse((
+                    Instant::now() + delay,
+                    self.seq,
+                    line.to_string(),
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_delayed(&self) -> Option<Instant> {
+        self.delayed.peek().map(|Reverse((at, _, _))| *at)
+    }
+
+    fn flush_delayed(&mut self) -> anyhow::Result<()> {
+        while self.next_delayed().is_some_and(|at| at <= Instant::now()) {
+            let Reverse((_, _, line)) = self.delayed.pop().expect("just peeked");
+            let msg: Message<Value> = serde_json::from_str(&line)?;
+            self.deliver(&msg)?;
+        }
+        Ok(())
+    }
+
+    fn shutdown(mut self) {
+        self.inputs.clear();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        for child in &mut self.children {
+            while Instant::now() < deadline {
+                if let Ok(Some(_)) = child.try_wait() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = &self.listener {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn init(cluster: &mut Cluster, rx: &Receiver<String>) -> anyhow::Result<()> {
+    for (i, &node) in cluster.nodes.clone().iter().enumerate() {
+        let init = Message {
+            src: NodeId::client(0),
+            dst: node,
+            body: Body {
+                id: Some(i),
+                in_reply_to: None,
+                payload: json!({"type": "init", "node_id": node, "node_ids": cluster.nodes}),
+            },
+        };
+        cluster.deliver(&init)?;
+    }
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut waiting = cluster.nodes.len();
+    while waiting > 0 {
+        let line = rx
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .with_context(|| format!("{} nodes never answered init", waiting))?;
+        match cluster.route(&line)? {
+            Some(reply) if reply.body.payload["type"] == "init_ok" => waiting -= 1,
+            Some(reply) => anyhow::bail!("expected init_ok, got {:?}", reply.body.payload),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+fn pump(
+    cluster: &mut Cluster,
+    clients: &mut Clients,
+    workload: &mut dyn Workload,
+    rx: &Receiver<String>,
+    start: Instant,
+    until: Instant,
+) -> anyhow::Result<()> {
+    loop {
+        cluster.flush_delayed()?;
+        let wake = cluster.next_delayed().map_or(until, |at| at.min(until));
+        match rx.recv_timeout(wake.saturating_duration_since(Instant::now())) {
+            Ok(line) => {
+                if let Some(reply) = cluster.route(&line)? {
+                    clients.receive(reply, workload);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) if Instant::now() >= until => break,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("every node has exited"),
+        }
+        clients.expire(start.elapsed(), workload);
+    }
+    clients.expire(start.elapsed(), workload);
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    let mut workload = workload::by_name(&args.workload)
+        .with_context(|| format!("unknown workload {}\n{}", args.workload, USAGE))?;
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let (mut cluster, rx) = Cluster::spawn(&args)?;
+    let start = Instant::now();
+    let mut clients = Clients::new(args.concurrency, Duration::from_secs(1));
+
+    init(&mut cluster, &rx)?;
+
+    for (node, payload) in workload.setup(&cluster.nodes) {
+        let request = clients.request(node, payload, start.elapsed());
+        cluster.deliver(&request)?;
+    }
+    let settle = Instant::now() + Duration::from_secs(1);
+    while clients.outstanding() > 0 && Instant::now() < settle {
+        pump(
+            &mut cluster,
+            &mut clients,
+            workload.as_mut(),
+            &rx,
+            start,
+            Instant::now() + Duration::from_millis(10),
+        )?;
+    }
+
+    let interval = Duration::from_secs_f64(1.0 / args.rate.max(0.001));
+    let stop = Instant::now() + args.time_limit;
+    let mut next_op = Instant::now();
+    while next_op < stop {
+        pump(
+            &mut cluster,
+            &mut clients,
+            workload.as_mut(),
+            &rx,
+            start,
+            next_op,
+        )?;
+        if clients.outstanding() < args.concurrency {
+            let node = cluster.nodes[rng.gen_range(0..cluster.nodes.len())];
+            let payload = workload.generate(&mut rng);
+            let request = clients.request(node, payload, start.elapsed());
+            cluster.deliver(&request)?;
+        }
+        next_op += interval;
+    }
+
+    if cluster.network.heal() {
+        eprintln!("{:?}: healed network", cluster.start.elapsed());
+    }
+    let recovered = Instant::now() + args.recovery;
+    pump(
+        &mut cluster,
+        &mut clients,
+        workload.as_mut(),
+        &rx,
+        start,
+        recovered,
+    )?;
+    for (node, payload) in workload.finish(&cluster.nodes) {
+        let request = clients.request(node, payload, start.elapsed());
+        cluster.deliver(&request)?;
+    }
+    let finished = Instant::now() + Duration::from_secs(1);
+    while clients.outstanding() > 0 && Instant::now() < finished {
+        pump(
+            &mut cluster,
+            &mut clients,
+            workload.as_mut(),
+            &rx,
+            start,
+            Instant::now() + Duration::from_millis(10),
+        )?;
+    }
+    clients.expire(
+        start.elapsed() + Duration::from_secs(3600),
+        workload.as_mut(),
+    );
+
+    let stats = clients.stats();
+    let delivered = cluster.delivered;
+    cluster.shutdown();
+    println!(
+        "{}: {} ok, {} failed, {} indeterminate, {} messages delivered",
+        workload.name(),
+        stats.ok,
+        stats.failed,
+        stats.indeterminate,
+        delivered
+    );
+    match workload.check() {
+        Ok(()) => {
+            println!("everything looks good");
+            Ok(())
+        }
+        Err(e) => {
+            println!("invalid: {:#}", e);
+            std::io::stdout().flush()?;
+            std::process::exit(1);
+        }
+    }
+}
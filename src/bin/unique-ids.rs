@@ -1,54 +1,41 @@
 use rustengan::*;
 
-use anyhow::Context;
-use serde::{Deserialize, Serialize};
-use std::io::{StdoutLock, Write};
+use serde::de::IgnoredAny;
+use serde::Serialize;
 use std::sync::mpsc::Sender;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
-enum Payload {
-    Generate,
-    GenerateOk {
-        #[serde(rename = "id")]
-        guid: String,
-    },
+#[derive(Debug, Clone, Serialize)]
+struct GenerateOk {
+    #[serde(rename = "id")]
+    guid: String,
 }
 
 struct UniqueNode {
-    node: String,
-    id: usize,
+    node: NodeId,
+    generated: usize,
 }
 
-impl Node<(), Payload> for UniqueNode {
-    fn from_init(_state: (), init: Init, _tx: Sender<Event<Payload>>) -> anyhow::Result<Self> {
+impl UniqueNode {
+    fn generate(&mut self, _: IgnoredAny) -> anyhow::Result<GenerateOk> {
+        let guid = format!("{}-{}", self.node, self.generated);
+        self.generated += 1;
+        Ok(GenerateOk { guid })
+    }
+}
+
+impl Routes<()> for UniqueNode {
+    fn from_init(_state: (), init: Init, _tx: Sender<Event<RawPayload>>) -> anyhow::Result<Self> {
         Ok(UniqueNode {
             node: init.node_id,
-            id: 1,
+            generated: 1,
         })
     }
 
-    fn step(&mut self, input: Event<Payload>, output: &mut StdoutLock) -> anyhow::Result<()> {
-        let Event::Message(input) = input else {
-            panic!("err");
-        };
-        let mut reply = input.into_reply(Some(&mut self.id));
-        match reply.body.payload {
-            Payload::Generate => {
-                let guid = format!("{}-{}", self.node, self.id);
-                reply.body.payload = Payload::GenerateOk { guid };
-                serde_json::to_writer(&mut *output, &reply)
-                    .context("serialize response to generate")?;
-                output.write_all(b"\n").context("write trailing newline")?;
-                self.id += 1;
-            }
-            Payload::GenerateOk { .. } => {}
-        }
-        Ok(())
+    fn router() -> Router<Self> {
+        Router::new().handle("generate", Self::generate)
     }
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<_, UniqueNode, _, _>(())
+    main_loop::<_, Routed<UniqueNode>, _, _>(())
 }
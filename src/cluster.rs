@@ -0,0 +1,161 @@
+use crate::{Init, InitError, NodeId};
+
+/// Points each node gets on the hash ring. More points spread keys more
+/// evenly over few nodes.
+const RING_POINTS: u32 = 64;
+
+/// The nodes of the cluster as a node sees them after `init`: itself, its
+/// peers, and the few things every node must agree on without talking to
+/// the others, like who leads and which node a key belongs to.
+///
+/// Every node builds the same `Cluster` from its `init`, sorting the node
+/// ids, so all of these answers are the same on every node.
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    me: NodeId,
+    index: usize,
+    /// Sorted and without duplicates.
+    nodes: Vec<NodeId>,
+    /// Points on the hash ring, sorted by hash.
+    ring: Vec<(u64, NodeId)>,
+}
+
+impl Cluster {
+    pub fn new(init: &Init) -> Result<Self, InitError> {
+        let mut nodes = init.node_ids.clone();
+        nodes.sort();
+        nodes.dedup();
+        let index = nodes
+            .binary_search(&init.node_id)
+            .map_err(|_| InitError::UnknownNode {
+                node_id: init.node_id,
+                node_ids: init.node_ids.clone(),
+            })?;
+        let mut ring: Vec<(u64, NodeId)> = nodes
+            .iter()
+            .flat_map(|&node| {
+                let name = node.to_string();
+                (0..RING_POINTS).map(move |point| {
+                    let mut bytes = name.as_bytes().to_vec();
+                    bytes.extend_from_slice(&point.to_le_bytes());
+                    (hash(&bytes), node)
+                })
+            })
+            .collect();
+        ring.sort();
+        Ok(Self {
+            me: init.node_id,
+            index,
+            nodes,
+            ring,
+        })
+    }
+
+    /// This node.
+    pub fn me(&self) -> NodeId {
+        self.me
+    }
+
+    /// Every node, this one included, in order.
+    pub fn nodes(&self) -> &[NodeId] {
+        &self.nodes
+    }
+
+    /// Every node but this one, in order.
+    pub fn peers(&self) -> impl Iterator<Item = NodeId> + '_ {
+        let me = self.me;
+        self.nodes.iter().copied().filter(move |&node| node != me)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Where this node is in [`nodes`](Self::nodes).
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Where `node` is in [`nodes`](Self::nodes), if it is a member.
+    pub fn index_of(&self, node: NodeId) -> Option<usize> {
+        self.nodes.binary_search(&node).ok()
+    }
+
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.index_of(node).is_some()
+    }
+
+    /// How many nodes, this one included, make a majority.
+    pub fn majority(&self) -> usize {
+        self.nodes.len() / 2 + 1
+    }
+
+    /// The node all nodes agree leads: the first one. Fixed for the life of
+    /// the cluster, so only as available as that node is.
+    pub fn leader(&self) -> NodeId {
+        self.nodes[0]
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.index == 0
+    }
+
+    /// The node `key` is placed on, by consistent hashing of its bytes.
+    pub fn owner<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> NodeId {
+        self.ring[self.ring_start(key)].1
+    }
+
+    pub fn owns<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> bool {
+        self.owner(key) == self.me
+    }
+
+    /// The first `count` distinct nodes on the ring from where `key` lands,
+    /// its owner first. Fewer if there are not that many nodes.
+    pub fn replicas<K: AsRef<[u8]> + ?Sized>(&self, key: &K, count: usize) -> Vec<NodeId> {
+        let count = count.min(self.nodes.len());
+        let start = self.ring_start(key);
+        let mut replicas = Vec::with_capacity(count);
+        for &(_, node) in self.ring[start..].iter().chain(&self.ring[..start]) {
+            if replicas.len() == count {
+                break;
+            }
+            if !replicas.contains(&node) {
+                replicas.push(node);
+            }
+        }
+        replicas
+    }
+
+    /// The first ring point at or after the hash of `key`, wrapping around.
+    fn ring_start<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> usize {
+        let at = hash(key.as_ref());
+        let start = self.ring.partition_point(|&(point, _)| point < at);
+        if start == self.ring.len() {
+            0
+        } else {
+            start
+        }
+    }
+}
+
+/// Hashes `bytes` with FNV-1a. Unlike `DefaultHasher`, FNV is specified,
+/// and the bytes are spelled out rather than left to `Hash`, so every node
+/// places keys alike. FNV alone barely spreads short, similar keys like `n1`
+/// and `n2` around the ring, hence the final mix.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        h ^= u64::from(b);
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    // The finalizer of MurmurHash3.
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
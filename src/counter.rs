@@ -0,0 +1,136 @@
+//! One node for the `g-counter` and `pn-counter` workloads: the count is a
+//! [`Counter`] CRDT that every node adds to locally and gossips to the
+//! others.
+
+use crate::crdt::{Delta, GCounter, PNCounter, Sync};
+use crate::gossip::Gossip;
+use crate::{Cluster, Event, Init, Node, NodeId, Outbox};
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+/// A CRDT that counts what `add` messages carry.
+pub trait Counter: Delta + Clone + PartialEq + Default {
+    /// What an `add` carries.
+    type Delta: Debug + Clone + Send + Serialize + DeserializeOwned + 'static;
+    /// What a `read` returns.
+    type Value: Debug + Clone + Send + Serialize + DeserializeOwned + 'static;
+
+    fn add(&mut self, node: NodeId, delta: Self::Delta);
+
+    fn value(&self) -> Self::Value;
+}
+
+impl Counter for GCounter {
+    type Delta = u64;
+    type Value = u64;
+
+    fn add(&mut self, node: NodeId, delta: u64) {
+        self.increment(node, delta);
+    }
+
+    fn value(&self) -> u64 {
+        GCounter::value(self)
+    }
+}
+
+impl Counter for PNCounter {
+    type Delta = i64;
+    type Value = i64;
+
+    fn add(&mut self, node: NodeId, delta: i64) {
+        PNCounter::add(self, node, delta);
+    }
+
+    fn value(&self) -> i64 {
+        PNCounter::value(self)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[serde(bound(
+    serialize = "C: Counter + Serialize",
+    deserialize = "C: Counter + DeserializeOwned"
+))]
+pub enum Payload<C: Counter> {
+    Add { delta: C::Delta },
+    AddOk,
+    Read,
+    ReadOk { value: C::Value },
+    Replicate(Sync<C>),
+}
+
+#[derive(Debug, Clone)]
+pub enum InjectedPayload {
+    Replicate,
+}
+
+pub struct CounterNode<C: Counter> {
+    node: NodeId,
+    counter: Gossip<C, Payload<C>>,
+}
+
+impl<C> Node<(), Payload<C>, InjectedPayload> for CounterNode<C>
+where
+    C: Counter + Serialize,
+{
+    fn from_init(
+        _state: (),
+        init: Init,
+        _tx: Sender<Event<Payload<C>, InjectedPayload>>,
+    ) -> anyhow::Result<Self> {
+        let cluster = Cluster::new(&init)?;
+        Ok(Self {
+            node: init.node_id,
+            counter: Gossip::new(
+                &cluster,
+                C::default(),
+                Default::default(),
+                Payload::Replicate,
+            )
+            .with_interval(Duration::from_millis(500)),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload<C>, InjectedPayload>,
+        outbox: &mut Outbox<InjectedPayload>,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Replicate) => self.counter.round(outbox)?,
+            Event::Message(input) => {
+                let mut reply = input.into_reply();
+                match reply.body.payload {
+                    Payload::Replicate(sync) => {
+                        self.counter.receive(reply.dst, &sync);
+                    }
+                    Payload::Read => {
+                        reply.body.payload = Payload::ReadOk {
+                            value: self.counter.state().value(),
+                        };
+                        outbox.send(&reply).context("read ok")?;
+                    }
+                    Payload::Add { delta } => {
+                        self.counter.state_mut().add(self.node, delta);
+                        reply.body.payload = Payload::AddOk;
+                        outbox.send(&reply).context("add ok")?;
+                    }
+                    Payload::ReadOk { .. } | Payload::AddOk => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn periodic(&self) -> Vec<(Duration, InjectedPayload)> {
+        vec![(self.counter.interval(), InjectedPayload::Replicate)]
+    }
+}
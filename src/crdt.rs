@@ -0,0 +1,689 @@
+//! State-based CRDTs: replicas change their own copy and exchange states,
+//! and [`Merge`] folds in whatever another replica sent, in any order and as
+//! often as the network delivers it. What they exchange need not be the
+//! whole state: [`Delta`] cuts out the part a peer is missing, and
+//! [`Replicator`] keeps track of what each peer has.
+
+use crate::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::{btree_set, BTreeMap, BTreeSet};
+
+/// A join-semilattice: `merge` is commutative, associative and idempotent,
+/// so replicas that have seen the same states agree however those states
+/// reached them.
+pub trait Merge {
+    fn merge(&mut self, other: &Self);
+
+    fn merged(mut self, other: &Self) -> Self
+    where
+        Self: Sized,
+    {
+        self.merge(other);
+        self
+    }
+}
+
+/// A CRDT that can tell what another replica is missing, so that only that
+/// has to be sent.
+pub trait Delta: Merge + Default {
+    /// The part of `self` not already in `known`: merging it into `known`
+    /// gives the same as merging all of `self`. The default, empty, state
+    /// if `known` has it all.
+    fn delta_since(&self, known: &Self) -> Self;
+}
+
+/// A counter that only goes up. Each node counts its own increments; the
+/// value is the sum over all nodes.
+///
+/// Serializes as the map from node to its count.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GCounter {
+    counts: BTreeMap<NodeId, u64>,
+}
+
+impl GCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn increment(&mut self, node: NodeId, by: u64) {
+        // No entry for nothing, so that equal counters compare equal.
+        if by > 0 {
+            *self.counts.entry(node).or_insert(0) += by;
+        }
+    }
+
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// What `node` has counted so far.
+    pub fn get(&self, node: NodeId) -> u64 {
+        self.counts.get(&node).copied().unwrap_or(0)
+    }
+
+    pub fn counts(&self) -> impl Iterator<Item = (NodeId, u64)> + '_ {
+        self.counts.iter().map(|(&node, &count)| (node, count))
+    }
+}
+
+impl Merge for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (&node, &count) in &other.counts {
+            let mine = self.counts.entry(node).or_insert(0);
+            *mine = (*mine).max(count);
+        }
+    }
+}
+
+impl Delta for GCounter {
+    fn delta_since(&self, known: &Self) -> Self {
+        let counts = self
+            .counts()
+            .filter(|&(node, count)| count > known.get(node))
+            .collect();
+        Self { counts }
+    }
+}
+
+/// A counter that goes both ways, as one [`GCounter`] of increments and
+/// one of decrements.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PNCounter {
+    #[serde(rename = "p")]
+    increments: GCounter,
+    #[serde(rename = "n")]
+    decrements: GCounter,
+}
+
+impl PNCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn increment(&mut self, node: NodeId, by: u64) {
+        self.increments.increment(node, by);
+    }
+
+    pub fn decrement(&mut self, node: NodeId, by: u64) {
+        self.decrements.increment(node, by);
+    }
+
+    /// Increments or decrements, depending on the sign of `delta`.
+    pub fn add(&mut self, node: NodeId, delta: i64) {
+        if delta >= 0 {
+            self.increment(node, delta.unsigned_abs());
+        } else {
+            self.decrement(node, delta.unsigned_abs());
+        }
+    }
+
+    pub fn value(&self) -> i64 {
+        self.increments.value() as i64 - self.decrements.value() as i64
+    }
+
+    pub fn increments(&self) -> &GCounter {
+        &self.increments
+    }
+
+    pub fn decrements(&self) -> &GCounter {
+        &self.decrements
+    }
+}
+
+impl Merge for PNCounter {
+    fn merge(&mut self, other: &Self) {
+        self.increments.merge(&other.increments);
+        self.decrements.merge(&other.decrements);
+    }
+}
+
+impl Delta for PNCounter {
+    fn delta_since(&self, known: &Self) -> Self {
+        Self {
+            increments: self.increments.delta_since(&known.increments),
+            decrements: self.decrements.delta_since(&known.decrements),
+        }
+    }
+}
+
+/// A set that can only grow.
+///
+/// Serializes as the sequence of its elements.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: Ord + Deserialize<'de>"))]
+pub struct GSet<T> {
+    elements: BTreeSet<T>,
+}
+
+impl<T> Default for GSet<T> {
+    fn default() -> Self {
+        Self {
+            elements: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord> GSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `value` is new.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.elements.insert(value)
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.elements.contains(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn iter(&self) -> btree_set::Iter<'_, T> {
+        self.elements.iter()
+    }
+}
+
+impl<T: Ord + Clone> Merge for GSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.elements.extend(other.elements.iter().cloned());
+    }
+}
+
+impl<T: Ord + Clone> Delta for GSet<T> {
+    fn delta_since(&self, known: &Self) -> Self {
+        self.elements.difference(&known.elements).cloned().collect()
+    }
+}
+
+impl<T: Ord> FromIterator<T> for GSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self {
+            elements: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<T: Ord> Extend<T> for GSet<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.elements.extend(iter);
+    }
+}
+
+impl<T> IntoIterator for GSet<T> {
+    type Item = T;
+    type IntoIter = btree_set::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.elements.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a GSet<T> {
+    type Item = &'a T;
+    type IntoIter = btree_set::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.elements.iter()
+    }
+}
+
+/// A set elements can be removed from, once: a removed element stays
+/// removed even if it is inserted again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: Ord + Deserialize<'de>"))]
+pub struct TwoPSet<T> {
+    added: GSet<T>,
+    removed: GSet<T>,
+}
+
+impl<T> Default for TwoPSet<T> {
+    fn default() -> Self {
+        Self {
+            added: GSet::default(),
+            removed: GSet::default(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> TwoPSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, value: T) {
+        self.added.insert(value);
+    }
+
+    /// Removes `value` for good. Only an element this replica has seen can
+    /// be removed; returns whether `value` was one.
+    pub fn remove(&mut self, value: &T) -> bool {
+        if !self.contains(value) {
+            return false;
+        }
+        self.removed.insert(value.clone());
+        true
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.added.contains(value) && !self.removed.contains(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.added
+            .iter()
+            .filter(|value| !self.removed.contains(value))
+    }
+}
+
+impl<T: Ord + Clone> Merge for TwoPSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.added.merge(&other.added);
+        self.removed.merge(&other.removed);
+    }
+}
+
+impl<T: Ord + Clone> Delta for TwoPSet<T> {
+    fn delta_since(&self, known: &Self) -> Self {
+        Self {
+            added: self.added.delta_since(&known.added),
+            removed: self.removed.delta_since(&known.removed),
+        }
+    }
+}
+
+/// Identifies one insert into an [`ORSet`]: the node that made it and how
+/// many inserts that node had made by then.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Dot(pub NodeId, pub u64);
+
+/// An observed-remove set: a remove takes out the inserts of an element it
+/// has seen, so an insert concurrent with it wins.
+///
+/// Removed inserts are kept as tombstones, so the state grows with every
+/// insert ever made.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: Ord + Deserialize<'de>"))]
+pub struct ORSet<T> {
+    /// How many inserts each node has made.
+    clock: GCounter,
+    inserts: BTreeSet<(T, Dot)>,
+    removed: BTreeSet<Dot>,
+}
+
+impl<T> Default for ORSet<T> {
+    fn default() -> Self {
+        Self {
+            clock: GCounter::default(),
+            inserts: BTreeSet::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> ORSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, node: NodeId, value: T) {
+        self.clock.increment(node, 1);
+        let dot = Dot(node, self.clock.get(node));
+        self.inserts.insert((value, dot));
+    }
+
+    /// Removes every insert of `value` this replica has seen. Returns
+    /// whether `value` was in the set.
+    pub fn remove(&mut self, value: &T) -> bool {
+        let live: Vec<Dot> = self.dots(value).collect();
+        self.removed.extend(&live);
+        !live.is_empty()
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.dots(value).next().is_some()
+    }
+
+    /// The elements in the set, each once.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        let mut last = None;
+        self.inserts
+            .iter()
+            .filter(|(_, dot)| !self.removed.contains(dot))
+            .map(|(value, _)| value)
+            .filter(move |&value| {
+                let new = last != Some(value);
+                last = Some(value);
+                new
+            })
+    }
+
+    /// The inserts of `value` that have not been removed.
+    fn dots<'a>(&'a self, value: &'a T) -> impl Iterator<Item = Dot> + 'a {
+        // `n0` is the least node id, so this is the least dot.
+        let first = (value.clone(), Dot(NodeId::server(0), 0));
+        self.inserts
+            .range(first..)
+            .take_while(move |(v, _)| v == value)
+            .map(|&(_, dot)| dot)
+            .filter(|dot| !self.removed.contains(dot))
+    }
+}
+
+impl<T: Ord + Clone> Merge for ORSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.clock.merge(&other.clock);
+        self.inserts.extend(other.inserts.iter().cloned());
+        self.removed.extend(&other.removed);
+    }
+}
+
+impl<T: Ord + Clone> Delta for ORSet<T> {
+    fn delta_since(&self, known: &Self) -> Self {
+        Self {
+            clock: self.clock.delta_since(&known.clock),
+            inserts: self.inserts.difference(&known.inserts).cloned().collect(),
+            removed: self.removed.difference(&known.removed).copied().collect(),
+        }
+    }
+}
+
+/// When a last-writer-wins write happened. Writes at the same `time` are
+/// ordered by node, so that every replica picks the same winner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Stamp {
+    pub time: u64,
+    pub node: NodeId,
+}
+
+impl Stamp {
+    pub fn new(time: u64, node: NodeId) -> Self {
+        Self { time, node }
+    }
+}
+
+/// A register holding whatever was written last, by [`Stamp`]. Should one
+/// node write twice with the same stamp, the greater value wins.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LwwRegister<T> {
+    entry: Option<(Stamp, T)>,
+}
+
+impl<T> Default for LwwRegister<T> {
+    fn default() -> Self {
+        Self { entry: None }
+    }
+}
+
+impl<T: Ord + Clone> LwwRegister<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `value` unless a later write is already in. Returns whether
+    /// `value` is now the value.
+    pub fn set(&mut self, value: T, stamp: Stamp) -> bool {
+        let newer = match &self.entry {
+            Some((current, old)) => (stamp, &value) > (*current, old),
+            None => true,
+        };
+        if newer {
+            self.entry = Some((stamp, value));
+        }
+        newer
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.entry.as_ref().map(|(_, value)| value)
+    }
+
+    /// The stamp of the write that won.
+    pub fn stamp(&self) -> Option<Stamp> {
+        self.entry.as_ref().map(|&(stamp, _)| stamp)
+    }
+}
+
+impl<T: Ord + Clone> Merge for LwwRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        if let Some((stamp, value)) = &other.entry {
+            self.set(value.clone(), *stamp);
+        }
+    }
+}
+
+impl<T: Ord + Clone> Delta for LwwRegister<T> {
+    fn delta_since(&self, known: &Self) -> Self {
+        let newer = match (&self.entry, &known.entry) {
+            (Some((stamp, value)), Some((known_stamp, known_value))) => {
+                (stamp, value) > (known_stamp, known_value)
+            }
+            (entry, _) => entry.is_some(),
+        };
+        if newer {
+            self.clone()
+        } else {
+            Self::default()
+        }
+    }
+}
+
+/// A map whose every key is an [`LwwRegister`]. A remove is a write of
+/// nothing, so it is kept as a tombstone and loses to later inserts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "K: Serialize, V: Serialize",
+    deserialize = "K: Ord + Deserialize<'de>, V: Deserialize<'de>"
+))]
+pub struct LwwMap<K, V> {
+    entries: BTreeMap<K, LwwRegister<Option<V>>>,
+}
+
+impl<K, V> Default for LwwMap<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Ord + Clone> LwwMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: K, value: V, stamp: Stamp) -> bool {
+        self.entries.entry(key).or_default().set(Some(value), stamp)
+    }
+
+    pub fn remove(&mut self, key: K, stamp: Stamp) -> bool {
+        self.entries.entry(key).or_default().set(None, stamp)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)?.get()?.as_ref()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.entries
+            .iter()
+            .filter_map(|(key, register)| Some((key, register.get()?.as_ref()?)))
+    }
+}
+
+impl<K: Ord + Clone, V: Ord + Clone> Merge for LwwMap<K, V> {
+    fn merge(&mut self, other: &Self) {
+        for (key, register) in &other.entries {
+            match self.entries.get_mut(key) {
+                Some(mine) => mine.merge(register),
+                None => {
+                    self.entries.insert(key.clone(), register.clone());
+                }
+            }
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Ord + Clone> Delta for LwwMap<K, V> {
+    fn delta_since(&self, known: &Self) -> Self {
+        let empty = LwwRegister::default();
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|(key, register)| {
+                let delta = register.delta_since(known.entries.get(key).unwrap_or(&empty));
+                delta.entry.is_some().then(|| (key.clone(), delta))
+            })
+            .collect();
+        Self { entries }
+    }
+}
+
+/// How many unacknowledged sends to a peer [`Replicator`] remembers. An
+/// ack for an older one is ignored, which only means the next delta to that
+/// peer is larger than it needs to be.
+const MAX_IN_FLIGHT: usize = 16;
+
+/// What one [`Replicator`] sends another: what the other is missing, and
+/// an ack for the last delta received from it. Either may be absent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sync<T> {
+    /// The delta, and the sequence number to ack it with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta: Option<(u64, T)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ack: Option<u64>,
+}
+
+/// Replicates a [`Delta`] CRDT by sending each peer only what it is not yet
+/// known to have.
+///
+/// What a peer has is what it acknowledged receiving from this replica, plus
+/// whatever it sent itself. Sends that are lost are simply covered by the
+/// next delta, which is again computed from what the peer acknowledged.
+/// Acks ride along with the next delta to the peer they are for, so a pair
+/// of replicas that both change exchange no more messages than they would
+/// sending whole states.
+#[derive(Debug, Clone)]
+pub struct Replicator<T> {
+    state: T,
+    peers: BTreeMap<NodeId, Peer<T>>,
+    next_seq: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Peer<T> {
+    /// What the peer is known to have.
+    known: T,
+    /// Deltas sent but not acknowledged yet, by sequence number.
+    in_flight: BTreeMap<u64, T>,
+    /// The last delta received from the peer, if it is not acked yet.
+    unacked: Option<u64>,
+}
+
+impl<T: Default> Default for Peer<T> {
+    fn default() -> Self {
+        Self {
+            known: T::default(),
+            in_flight: BTreeMap::new(),
+            unacked: None,
+        }
+    }
+}
+
+impl<T: Delta + Clone + PartialEq> Replicator<T> {
+    pub fn new(state: T) -> Self {
+        Self {
+            state,
+            peers: BTreeMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    /// For changes made by this replica.
+    pub fn state_mut(&mut self) -> &mut T {
+        &mut self.state
+    }
+
+    /// Starts replicating to `peer`, which is otherwise done once the first
+    /// sync to or from it.
+    pub fn add_peer(&mut self, peer: NodeId) {
+        self.peers.entry(peer).or_default();
+    }
+
+    /// Every peer replicated to or from so far. Those that sent something
+    /// are owed an ack, so should be synced with even if they are not
+    /// otherwise replicated to.
+    pub fn peers(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.peers.keys().copied()
+    }
+
+    /// Whether `peer` sent a delta this replica has not acked yet.
+    pub fn owes_ack(&self, peer: NodeId) -> bool {
+        self.peers
+            .get(&peer)
+            .is_some_and(|entry| entry.unacked.is_some())
+    }
+
+    /// Forgets what `peer` is known to have, so that the next sync to it
+    /// carries the whole state.
+    pub fn forget(&mut self, peer: NodeId) {
+        if let Some(entry) = self.peers.get_mut(&peer) {
+            entry.known = T::default();
+        }
+    }
+
+    /// What to send `peer` next, or `None` if it is known to have everything
+    /// and is owed no ack.
+    pub fn sync_for(&mut self, peer: NodeId) -> Option<Sync<T>> {
+        let entry = self.peers.entry(peer).or_default();
+        let delta = self.state.delta_since(&entry.known);
+        let delta = if delta == T::default() {
+            None
+        } else {
+            self.next_seq += 1;
+            entry.in_flight.insert(self.next_seq, delta.clone());
+            while entry.in_flight.len() > MAX_IN_FLIGHT {
+                entry.in_flight.pop_first();
+            }
+            Some((self.next_seq, delta))
+        };
+        let ack = entry.unacked.take();
+        (delta.is_some() || ack.is_some()).then_some(Sync { delta, ack })
+    }
+
+    /// Takes in a sync from `peer`: merges its delta, which `peer` therefore
+    /// has, and notes its ack.
+    pub fn receive(&mut self, peer: NodeId, sync: &Sync<T>) {
+        let entry = self.peers.entry(peer).or_default();
+        if let Some((seq, delta)) = &sync.delta {
+            self.state.merge(delta);
+            entry.known.merge(delta);
+            entry.unacked = entry.unacked.max(Some(*seq));
+        }
+        if let Some(seq) = sync.ack {
+            // That delta and what `peer` was known to have cover every delta
+            // sent before it, so those count as received too.
+            let later = entry.in_flight.split_off(&(seq + 1));
+            for delta in std::mem::replace(&mut entry.in_flight, later).into_values() {
+                entry.known.merge(&delta);
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Reading just enough of a message to decide what to do with it. Routing,
+//! rpc replies and turning away unknown types only need the header, so the
+//! payload stays unparsed JSON until whoever handles the message asks for it.
+
+use crate::{Body, Message, NodeId};
+use serde::de::DeserializeOwned;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::value::RawValue;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+
+/// The header of a message, with the body left as raw JSON borrowed from
+/// the line it was read from.
+#[derive(Debug)]
+pub struct Envelope<'a> {
+    pub src: NodeId,
+    pub dst: NodeId,
+    pub id: Option<usize>,
+    pub in_reply_to: Option<usize>,
+    /// The body's `type`, if it has one.
+    pub kind: Option<Cow<'a, str>>,
+    /// The whole body, `msg_id` and `in_reply_to` included.
+    pub body: &'a RawValue,
+}
+
+#[derive(Deserialize)]
+struct Outer<'a> {
+    src: NodeId,
+    #[serde(rename = "dest")]
+    dst: NodeId,
+    #[serde(borrow)]
+    body: &'a RawValue,
+}
+
+#[derive(Deserialize)]
+struct Header<'a> {
+    #[serde(rename = "msg_id", default)]
+    id: Option<usize>,
+    #[serde(default)]
+    in_reply_to: Option<usize>,
+    #[serde(rename = "type", borrow, default)]
+    kind: Option<Cow<'a, str>>,
+}
+
+impl<'a> Envelope<'a> {
+    pub fn parse(line: &'a str) -> serde_json::Result<Self> {
+        let outer: Outer = serde_json::from_str(line)?;
+        if !outer.body.get().starts_with('{') {
+            return Err(serde::de::Error::custom("body is not an object"));
+        }
+        let header: Header = serde_json::from_str(outer.body.get())?;
+        Ok(Envelope {
+            src: outer.src,
+            dst: outer.dst,
+            id: header.id,
+            in_reply_to: header.in_reply_to,
+            kind: header.kind,
+            body: outer.body,
+        })
+    }
+
+    pub fn kind(&self) -> &str {
+        self.kind.as_deref().unwrap_or("untyped")
+    }
+
+    /// Deserializes the body as `P`. `P` sees the whole body, but the
+    /// payloads nodes use ignore `msg_id` and `in_reply_to`.
+    pub fn payload<P: DeserializeOwned>(&self) -> serde_json::Result<P> {
+        serde_json::from_str(self.body.get())
+    }
+
+    /// The message with `payload` for its body.
+    pub fn with_payload<P>(&self, payload: P) -> Message<P> {
+        Message {
+            src: self.src,
+            dst: self.dst,
+            body: Body {
+                id: self.id,
+                in_reply_to: self.in_reply_to,
+                payload,
+            },
+        }
+    }
+
+    /// The whole message, its body parsed as JSON as a `Message<Value>`
+    /// would have it.
+    pub fn to_value(&self) -> serde_json::Result<Message<Value>> {
+        let mut payload: Value = self.payload()?;
+        if let Value::Object(fields) = &mut payload {
+            fields.remove("msg_id");
+            fields.remove("in_reply_to");
+        }
+        Ok(self.with_payload(payload))
+    }
+}
+
+/// A payload kept as the JSON it arrived as, for nodes that dispatch on
+/// `type`, like those built on [`Router`](crate::Router), and only parse the
+/// messages they handle.
+///
+/// Deserializes straight from JSON text only, which is how the runtime reads
+/// payloads; it does not work inside a `#[serde(flatten)]`.
+#[derive(Debug, Clone)]
+pub struct RawPayload {
+    kind: Option<String>,
+    raw: Box<RawValue>,
+}
+
+impl RawPayload {
+    pub fn kind(&self) -> &str {
+        self.kind.as_deref().unwrap_or("untyped")
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_str(self.raw.get())
+    }
+
+    /// The body as it arrived.
+    pub fn get(&self) -> &str {
+        self.raw.get()
+    }
+}
+
+impl<'de> Deserialize<'de> for RawPayload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = Box::<RawValue>::deserialize(deserializer)?;
+        let header: Header = serde_json::from_str(raw.get()).map_err(serde::de::Error::custom)?;
+        Ok(RawPayload {
+            kind: header.kind.map(Cow::into_owned),
+            raw,
+        })
+    }
+}
+
+/// Serializes as the payload fields, to go in a [`Body`] like any other.
+impl Serialize for RawPayload {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let fields: Map<String, Value> = self.parse().map_err(serde::ser::Error::custom)?;
+        let mut map = serializer.serialize_map(None)?;
+        for (key, value) in &fields {
+            if key != "msg_id" && key != "in_reply_to" {
+                map.serialize_entry(key, value)?;
+            }
+        }
+        map.end()
+    }
+}
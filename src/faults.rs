@@ -0,0 +1,243 @@
+use crate::NodeId;
+use anyhow::Context;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How the servers are split whenever a partition schedule cuts the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// Two halves of (nearly) equal size that cannot reach each other.
+    RandomHalves,
+    /// A random minority cut off from the remaining majority.
+    MajorityMinority,
+    /// Two halves that can only talk through one bridge node in between.
+    Bridge,
+}
+
+impl FromStr for PartitionKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "halves" | "random-halves" => PartitionKind::RandomHalves,
+            "majority" | "majority-minority" => PartitionKind::MajorityMinority,
+            "bridge" => PartitionKind::Bridge,
+            _ => anyhow::bail!(
+                "unknown partition {}, expected halves, majority or bridge",
+                s
+            ),
+        })
+    }
+}
+
+/// Alternates between a healed network and a fresh partition of `kind`
+/// every `interval`, starting healed.
+#[derive(Debug, Clone, Copy)]
+pub struct PartitionSchedule {
+    pub kind: PartitionKind,
+    pub interval: Duration,
+}
+
+/// Extra latency added to every message between servers. Random delays are
+/// also what reorders messages on a link.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delay {
+    None,
+    Uniform(Duration, Duration),
+    Exponential(Duration),
+}
+
+impl Delay {
+    fn sample(&self, rng: &mut StdRng) -> Duration {
+        match *self {
+            Delay::None => Duration::ZERO,
+            Delay::Uniform(min, max) if max > min => rng.gen_range(min..=max),
+            Delay::Uniform(min, _) => min,
+            Delay::Exponential(mean) => {
+                let u: f64 = rng.gen_range(f64::EPSILON..1.0);
+                mean.mul_f64(-u.ln())
+            }
+        }
+    }
+}
+
+/// Parses `none`, `uniform:MIN_MS:MAX_MS` or `exp:MEAN_MS`.
+impl FromStr for Delay {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let ms = |v: &str| -> anyhow::Result<Duration> {
+            Ok(Duration::from_secs_f64(
+                v.parse::<f64>()
+                    .with_context(|| format!("bad delay {}", s))?
+                    / 1000.0,
+            ))
+        };
+        Ok(match parts.as_slice() {
+            ["none"] => Delay::None,
+            ["uniform", min, max] => Delay::Uniform(ms(min)?, ms(max)?),
+            ["exp", mean] => Delay::Exponential(ms(mean)?),
+            _ => anyhow::bail!(
+                "unknown delay {}, expected none, uniform:MIN:MAX or exp:MEAN",
+                s
+            ),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Faults {
+    /// Probability that a message between two servers is lost.
+    pub drop: f64,
+    /// Loss probabilities that replace `drop` for messages from the first
+    /// server to the second, for links that are worse (or better) than the
+    /// rest. Each direction is set on its own.
+    pub link_drop: HashMap<(NodeId, NodeId), f64>,
+    /// Probability that a message between two servers arrives twice.
+    pub duplicate: f64,
+    pub delay: Delay,
+    pub partition: Option<PartitionSchedule>,
+}
+
+impl Faults {
+    /// The probability that a message from `src` to `dst` is lost.
+    pub fn drop_on(&self, src: NodeId, dst: NodeId) -> f64 {
+        self.link_drop
+            .get(&(src, dst))
+            .copied()
+            .unwrap_or(self.drop)
+    }
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Self {
+            drop: 0.0,
+            link_drop: HashMap::new(),
+            duplicate: 0.0,
+            delay: Delay::None,
+            partition: None,
+        }
+    }
+}
+
+/// Decides the fate of every message between two servers: lost, delayed or
+/// duplicated, and whether a partition currently separates them. Traffic
+/// involving clients or services is never faulted.
+pub struct Network {
+    faults: Faults,
+    rng: StdRng,
+    next_change: Option<Duration>,
+    blocked: HashMap<NodeId, HashSet<NodeId>>,
+}
+
+impl Network {
+    pub fn new(faults: Faults, seed: u64) -> Self {
+        Self {
+            next_change: faults.partition.map(|p| p.interval),
+            faults,
+            rng: StdRng::seed_from_u64(seed),
+            blocked: HashMap::new(),
+        }
+    }
+
+    pub fn is_partitioned(&self) -> bool {
+        !self.blocked.is_empty()
+    }
+
+    /// Heals any partition and stops the schedule, so that the cluster can
+    /// recover before the final checks.
+    pub fn heal(&mut self) -> bool {
+        self.next_change = None;
+        let was_partitioned = self.is_partitioned();
+        self.blocked.clear();
+        was_partitioned
+    }
+
+    /// Moves the partition schedule forward to `now`. Returns a description
+    /// of the new network shape if it changed.
+    pub fn advance(&mut self, now: Duration, nodes: &[NodeId]) -> Option<String> {
+        let schedule = self.faults.partition?;
+        let mut changed = None;
+        while let Some(at) = self.next_change.filter(|at| *at <= now) {
+            self.next_change = Some(at + schedule.interval);
+            changed = Some(if self.is_partitioned() {
+                self.blocked.clear();
+                "healed network".to_string()
+            } else {
+                self.partition(schedule.kind, nodes)
+            });
+        }
+        changed
+    }
+
+    /// Extra delays after which copies of a message from `src` to `dst`
+    /// arrive: none if it is lost, two if it is duplicated.
+    pub fn deliveries(&mut self, src: NodeId, dst: NodeId) -> Vec<Duration> {
+        if !src.is_server() || !dst.is_server() {
+            return vec![Duration::ZERO];
+        }
+        if self.blocked.get(&src).is_some_and(|b| b.contains(&dst)) {
+            return Vec::new();
+        }
+        let drop = self.faults.drop_on(src, dst);
+        if drop > 0.0 && self.rng.gen_bool(drop.min(1.0)) {
+            return Vec::new();
+        }
+        let copies =
+            if self.faults.duplicate > 0.0 && self.rng.gen_bool(self.faults.duplicate.min(1.0)) {
+                2
+            } else {
+                1
+            };
+        (0..copies)
+            .map(|_| self.faults.delay.sample(&mut self.rng))
+            .collect()
+    }
+
+    fn partition(&mut self, kind: PartitionKind, nodes: &[NodeId]) -> String {
+        let mut shuffled = nodes.to_vec();
+        shuffled.shuffle(&mut self.rng);
+        let components: Vec<Vec<NodeId>> = match kind {
+            PartitionKind::RandomHalves => {
+                let right = shuffled.split_off(shuffled.len() / 2);
+                vec![shuffled, right]
+            }
+            PartitionKind::MajorityMinority => {
+                let majority = shuffled.split_off(shuffled.len().saturating_sub(1) / 2);
+                vec![shuffled, majority]
+            }
+            PartitionKind::Bridge => {
+                let bridge = shuffled.pop();
+                let mut right = shuffled.split_off(shuffled.len() / 2);
+                let mut left = shuffled;
+                if let Some(bridge) = bridge {
+                    left.push(bridge);
+                    right.push(bridge);
+                }
+                vec![left, right]
+            }
+        };
+        for a in nodes {
+            for b in nodes {
+                let together = components.iter().any(|c| c.contains(a) && c.contains(b));
+                if !together {
+                    self.blocked.entry(*a).or_default().insert(*b);
+                }
+            }
+        }
+        let shape: Vec<String> = components
+            .iter()
+            .map(|c| {
+                let ids: Vec<String> = c.iter().map(NodeId::to_string).collect();
+                format!("[{}]", ids.join(" "))
+            })
+            .collect();
+        format!("partitioned network into {}", shape.join(" "))
+    }
+}
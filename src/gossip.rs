@@ -0,0 +1,171 @@
+//! Replicating state by gossip: every so often, each node pushes to some of
+//! its peers what they are missing of a [`Delta`] CRDT, and merges whatever
+//! its peers push back.
+//!
+//! A node keeps a [`Gossip`], calls [`Gossip::round`] from a periodic timer
+//! and hands it the [`Sync`]s other nodes send. Which peers a round goes to
+//! is up to a [`PeerSelection`].
+
+use crate::crdt::{Delta, Replicator, Sync};
+use crate::{Body, Cluster, Message, NodeId, Outbox};
+use anyhow::Context;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Picks the peers a gossip round pushes to. Peers owed an ack are synced
+/// with as well, whoever is picked.
+pub trait PeerSelection {
+    fn select(&mut self, peers: &[NodeId], rng: &mut StdRng) -> Vec<NodeId>;
+}
+
+/// Every peer, every round.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllPeers;
+
+impl PeerSelection for AllPeers {
+    fn select(&mut self, peers: &[NodeId], _rng: &mut StdRng) -> Vec<NodeId> {
+        peers.to_vec()
+    }
+}
+
+/// `fanout` peers picked at random each round.
+#[derive(Debug, Clone, Copy)]
+pub struct RandomPeers {
+    pub fanout: usize,
+}
+
+impl PeerSelection for RandomPeers {
+    fn select(&mut self, peers: &[NodeId], rng: &mut StdRng) -> Vec<NodeId> {
+        peers.choose_multiple(rng, self.fanout).copied().collect()
+    }
+}
+
+/// A fixed set of peers, such as the neighbors a `topology` message names.
+#[derive(Debug, Clone, Default)]
+pub struct Neighbors(pub Vec<NodeId>);
+
+impl PeerSelection for Neighbors {
+    fn select(&mut self, _peers: &[NodeId], _rng: &mut StdRng) -> Vec<NodeId> {
+        self.0.clone()
+    }
+}
+
+/// Gossips a `T` with the rest of the cluster, over messages with payload
+/// `P`.
+///
+/// Each round sends every selected peer only the delta it is not known to
+/// have, and acks what arrived since the last round (see [`Replicator`]).
+/// Every few rounds one random peer also gets the whole state, whatever it
+/// is known to have. That anti-entropy push reaches peers the selection
+/// never picks, and repairs a peer that lost state the others count on.
+pub struct Gossip<T, P, S = AllPeers> {
+    me: NodeId,
+    peers: Vec<NodeId>,
+    replicator: Replicator<T>,
+    selection: S,
+    /// Wraps a sync in the payload of the node's gossip message.
+    wrap: fn(Sync<T>) -> P,
+    interval: Duration,
+    anti_entropy_every: u64,
+    rounds: u64,
+    rng: StdRng,
+}
+
+impl<T, P, S> Gossip<T, P, S>
+where
+    T: Delta + Clone + PartialEq,
+    P: Serialize,
+    S: PeerSelection,
+{
+    pub fn new(cluster: &Cluster, state: T, selection: S, wrap: fn(Sync<T>) -> P) -> Self {
+        let me = cluster.me();
+        Self {
+            me,
+            peers: cluster.peers().collect(),
+            replicator: Replicator::new(state),
+            selection,
+            wrap,
+            interval: Duration::from_millis(300),
+            anti_entropy_every: 10,
+            rounds: 0,
+            rng: StdRng::seed_from_u64(cluster.index() as u64),
+        }
+    }
+
+    /// How often the node should call [`round`](Self::round). Defaults to
+    /// 300ms.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Pushes the whole state to a random peer every `rounds` rounds, or
+    /// never if `rounds` is 0. Defaults to every 10 rounds.
+    pub fn with_anti_entropy_every(mut self, rounds: u64) -> Self {
+        self.anti_entropy_every = rounds;
+        self
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn state(&self) -> &T {
+        self.replicator.state()
+    }
+
+    /// For changes made by this node. They go out with the next round.
+    pub fn state_mut(&mut self) -> &mut T {
+        self.replicator.state_mut()
+    }
+
+    pub fn selection_mut(&mut self) -> &mut S {
+        &mut self.selection
+    }
+
+    /// Pushes to the peers of this round whatever they are missing.
+    pub fn round<IP>(&mut self, outbox: &mut Outbox<IP>) -> anyhow::Result<()> {
+        self.rounds += 1;
+        let mut targets = self.selection.select(&self.peers, &mut self.rng);
+        if self.anti_entropy_every > 0 && self.rounds.is_multiple_of(self.anti_entropy_every) {
+            if let Some(&peer) = self.peers.choose(&mut self.rng) {
+                self.replicator.forget(peer);
+                targets.push(peer);
+            }
+        }
+        targets.extend(
+            self.replicator
+                .peers()
+                .filter(|&peer| self.replicator.owes_ack(peer)),
+        );
+        targets.sort();
+        targets.dedup();
+
+        for peer in targets {
+            let Some(sync) = self.replicator.sync_for(peer) else {
+                continue;
+            };
+            let gossip = Message {
+                src: self.me,
+                dst: peer,
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    payload: (self.wrap)(sync),
+                },
+            };
+            outbox
+                .send(&gossip)
+                .with_context(|| format!("gossip to {}", peer))?;
+        }
+        Ok(())
+    }
+
+    /// Merges a sync `from` sent.
+    pub fn receive(&mut self, from: NodeId, sync: &Sync<T>) {
+        self.replicator.receive(from, sync);
+    }
+}
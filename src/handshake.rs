@@ -0,0 +1,112 @@
+use crate::{error_reply, Body, ErrorBody, ErrorCode, Init, InitPayload, Message, NodeId, Outbox};
+use anyhow::Context;
+use serde_json::Value;
+use std::fmt;
+
+/// How many lines arriving before `init` are held back to be handled once
+/// the node is up. Requests beyond that get `temporarily-unavailable`.
+pub const EARLY_LIMIT: usize = 1024;
+
+/// Why a node could not be initialized.
+#[derive(Debug)]
+pub enum InitError {
+    /// Input ended before an `init` arrived.
+    Eof,
+    /// The `init` body did not parse.
+    Malformed(serde_json::Error),
+    /// `node_id` is not one of `node_ids`.
+    UnknownNode {
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
+    },
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::Eof => write!(f, "input ended before init"),
+            InitError::Malformed(e) => write!(f, "malformed init: {}", e),
+            InitError::UnknownNode { node_id, node_ids } => {
+                write!(f, "{} is not one of {:?}", node_id, node_ids)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InitError::Malformed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Waits for `init`, keeping whatever arrives before it.
+#[derive(Debug, Default)]
+pub(crate) struct Handshake {
+    early: Vec<String>,
+}
+
+impl Handshake {
+    /// Looks at one line read before the node is initialized and returns the
+    /// `init` request once it arrives. An invalid `init` is answered with
+    /// `malformed-request` and fails the handshake.
+    pub(crate) fn offer<IP>(
+        &mut self,
+        line: String,
+        outbox: &mut Outbox<IP>,
+        strict: bool,
+    ) -> anyhow::Result<Option<Message<Init>>> {
+        let raw: Message<Value> = match serde_json::from_str(&line) {
+            Ok(raw) => raw,
+            Err(e) if strict => return Err(e).context("input could not be deserialized"),
+            Err(e) => {
+                outbox.log(format!("dropping malformed input: {}", e));
+                return Ok(None);
+            }
+        };
+        if raw.body.payload["type"] != "init" {
+            if self.early.len() < EARLY_LIMIT {
+                self.early.push(line);
+            } else if let Some(reply) = error_reply(
+                &raw,
+                ErrorBody::new(ErrorCode::TemporarilyUnavailable, "node is not initialized"),
+            ) {
+                outbox.log(format!("turning away {} before init", raw.src));
+                outbox.send(&reply)?;
+            }
+            return Ok(None);
+        }
+        let error = match serde_json::from_value(raw.body.payload.clone()) {
+            Ok(InitPayload::Init(init)) if init.node_ids.contains(&init.node_id) => {
+                return Ok(Some(Message {
+                    src: raw.src,
+                    dst: raw.dst,
+                    body: Body {
+                        id: raw.body.id,
+                        in_reply_to: raw.body.in_reply_to,
+                        payload: init,
+                    },
+                }));
+            }
+            Ok(InitPayload::Init(Init { node_id, node_ids })) => {
+                InitError::UnknownNode { node_id, node_ids }
+            }
+            Ok(InitPayload::InitOk) => unreachable!("type is init"),
+            Err(e) => InitError::Malformed(e),
+        };
+        if let Some(reply) = error_reply(
+            &raw,
+            ErrorBody::new(ErrorCode::MalformedRequest, error.to_string()),
+        ) {
+            outbox.send(&reply)?;
+        }
+        Err(error.into())
+    }
+
+    /// The lines that arrived before `init`, in order.
+    pub(crate) fn into_early(self) -> Vec<String> {
+        self.early
+    }
+}
@@ -0,0 +1,351 @@
+use crate::{Body, ErrorCode, Message, NodeId, Outbox, Rpc, RpcError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+/// Wire protocol shared by Maelstrom's `seq-kv`, `lin-kv` and `lww-kv`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum KvPayload<K = Value, V = Value> {
+    Read {
+        key: K,
+    },
+    ReadOk {
+        value: V,
+    },
+    Write {
+        key: K,
+        value: V,
+    },
+    WriteOk,
+    Cas {
+        key: K,
+        from: V,
+        to: V,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+}
+
+#[derive(Debug)]
+pub enum KvError {
+    KeyDoesNotExist,
+    PreconditionFailed,
+    Rpc(RpcError),
+}
+
+impl From<RpcError> for KvError {
+    fn from(e: RpcError) -> Self {
+        match e {
+            RpcError::Remote(e) if e.code == ErrorCode::KeyDoesNotExist => KvError::KeyDoesNotExist,
+            RpcError::Remote(e) if e.code == ErrorCode::PreconditionFailed => {
+                KvError::PreconditionFailed
+            }
+            e => KvError::Rpc(e),
+        }
+    }
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::KeyDoesNotExist => write!(f, "key does not exist"),
+            KvError::PreconditionFailed => write!(f, "precondition failed"),
+            KvError::Rpc(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+pub trait KvService {
+    const NAME: &'static str;
+}
+
+pub struct SeqKvService;
+pub struct LinKvService;
+pub struct LwwKvService;
+
+impl KvService for SeqKvService {
+    const NAME: &'static str = "seq-kv";
+}
+
+impl KvService for LinKvService {
+    const NAME: &'static str = "lin-kv";
+}
+
+impl KvService for LwwKvService {
+    const NAME: &'static str = "lww-kv";
+}
+
+pub type SeqKv<Payload, InjectedPayload> = Kv<SeqKvService, Payload, InjectedPayload>;
+pub type LinKv<Payload, InjectedPayload> = Kv<LinKvService, Payload, InjectedPayload>;
+pub type LwwKv<Payload, InjectedPayload> = Kv<LwwKvService, Payload, InjectedPayload>;
+
+/// Client for one of the kv services. Every operation sends its request
+/// through `rpc` and injects what `k` makes of the outcome back into the node.
+pub struct Kv<S, Payload, InjectedPayload> {
+    node: NodeId,
+    /// `S::NAME`, interned once.
+    dst: NodeId,
+    rpc: Rpc<Payload, InjectedPayload>,
+    timeout: Duration,
+    service: PhantomData<S>,
+}
+
+impl<S, Payload, InjectedPayload> Clone for Kv<S, Payload, InjectedPayload> {
+    fn clone(&self) -> Self {
+        Self {
+            node: self.node,
+            dst: self.dst,
+            rpc: self.rpc.clone(),
+            timeout: self.timeout,
+            service: PhantomData,
+        }
+    }
+}
+
+impl<S, Payload, InjectedPayload> Kv<S, Payload, InjectedPayload>
+where
+    S: KvService,
+    Payload: Send + 'static,
+    InjectedPayload: Send + 'static,
+{
+    pub fn new(node: NodeId, rpc: Rpc<Payload, InjectedPayload>) -> Self {
+        Self {
+            node,
+            dst: NodeId::from(S::NAME),
+            rpc,
+            timeout: Duration::from_secs(1),
+            service: PhantomData,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn read<K, V, F>(
+        &self,
+        key: K,
+        outbox: &mut Outbox<InjectedPayload>,
+        k: F,
+    ) -> anyhow::Result<()>
+    where
+        K: Serialize,
+        V: DeserializeOwned,
+        F: FnOnce(Result<V, KvError>) -> InjectedPayload + Send + 'static,
+    {
+        self.call(
+            KvPayload::<K, ()>::Read { key },
+            outbox,
+            move |reply: Result<KvPayload<Value, V>, KvError>| match reply {
+                Ok(KvPayload::ReadOk { value }) => k(Ok(value)),
+                Ok(other) => k(Err(unexpected(other))),
+                Err(e) => k(Err(e)),
+            },
+        )
+    }
+
+    pub fn write<K, V, F>(
+        &self,
+        key: K,
+        value: V,
+        outbox: &mut Outbox<InjectedPayload>,
+        k: F,
+    ) -> anyhow::Result<()>
+    where
+        K: Serialize,
+        V: Serialize,
+        F: FnOnce(Result<(), KvError>) -> InjectedPayload + Send + 'static,
+    {
+        self.call(
+            KvPayload::Write { key, value },
+            outbox,
+            move |reply: Result<KvPayload, KvError>| match reply {
+                Ok(KvPayload::WriteOk) => k(Ok(())),
+                Ok(other) => k(Err(unexpected(other))),
+                Err(e) => k(Err(e)),
+            },
+        )
+    }
+
+    /// Sets `key` to `to` if it currently holds `from`. With
+    /// `create_if_not_exists` a missing key is created instead of failing
+    /// with `KvError::KeyDoesNotExist`.
+    pub fn cas<K, V, F>(
+        &self,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+        outbox: &mut Outbox<InjectedPayload>,
+        k: F,
+    ) -> anyhow::Result<()>
+    where
+        K: Serialize,
+        V: Serialize,
+        F: FnOnce(Result<(), KvError>) -> InjectedPayload + Send + 'static,
+    {
+        self.call(
+            KvPayload::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            },
+            outbox,
+            move |reply: Result<KvPayload, KvError>| match reply {
+                Ok(KvPayload::CasOk) => k(Ok(())),
+                Ok(other) => k(Err(unexpected(other))),
+                Err(e) => k(Err(e)),
+            },
+        )
+    }
+
+    fn call<K, V, R, F>(
+        &self,
+        request: KvPayload<K, V>,
+        outbox: &mut Outbox<InjectedPayload>,
+        k: F,
+    ) -> anyhow::Result<()>
+    where
+        K: Serialize,
+        V: Serialize,
+        R: DeserializeOwned,
+        F: FnOnce(Result<R, KvError>) -> InjectedPayload + Send + 'static,
+    {
+        let request = Message {
+            src: self.node,
+            dst: self.dst,
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                payload: request,
+            },
+        };
+        self.rpc.call(
+            &request,
+            self.timeout,
+            outbox,
+            move |reply: Result<Message<R>, _>| {
+                k(reply.map(|reply| reply.body.payload).map_err(KvError::from))
+            },
+        )
+    }
+}
+
+/// Client for one of the kv services, for nodes run by
+/// [`async_main_loop`](crate::async_main_loop).
+#[cfg(feature = "async")]
+pub struct AsyncKv<S> {
+    handle: crate::Handle,
+    dst: NodeId,
+    timeout: Duration,
+    service: PhantomData<S>,
+}
+
+#[cfg(feature = "async")]
+impl<S> Clone for AsyncKv<S> {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle.clone(),
+            dst: self.dst,
+            timeout: self.timeout,
+            service: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<S: KvService> AsyncKv<S> {
+    pub fn new(handle: crate::Handle) -> Self {
+        Self {
+            handle,
+            dst: NodeId::from(S::NAME),
+            timeout: Duration::from_secs(1),
+            service: PhantomData,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn read<K, V>(&self, key: K) -> Result<V, KvError>
+    where
+        K: Serialize,
+        V: DeserializeOwned,
+    {
+        match self.call(KvPayload::<K, ()>::Read { key }).await? {
+            KvPayload::<Value, V>::ReadOk { value } => Ok(value),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn write<K, V>(&self, key: K, value: V) -> Result<(), KvError>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        match self.call(KvPayload::Write { key, value }).await? {
+            KvPayload::<Value, Value>::WriteOk => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Sets `key` to `to` if it currently holds `from`, as [`Kv::cas`] does.
+    pub async fn cas<K, V>(
+        &self,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+    ) -> Result<(), KvError>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let request = KvPayload::Cas {
+            key,
+            from,
+            to,
+            create_if_not_exists,
+        };
+        match self.call(request).await? {
+            KvPayload::<Value, Value>::CasOk => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn call<K, V, R>(&self, request: KvPayload<K, V>) -> Result<R, KvError>
+    where
+        K: Serialize,
+        V: Serialize,
+        R: DeserializeOwned,
+    {
+        let reply: Message<R> = self.handle.call(self.dst, request, self.timeout).await?;
+        Ok(reply.body.payload)
+    }
+}
+
+fn unexpected<K, V>(reply: KvPayload<K, V>) -> KvError {
+    let kind = match reply {
+        KvPayload::Read { .. } => "read",
+        KvPayload::ReadOk { .. } => "read_ok",
+        KvPayload::Write { .. } => "write",
+        KvPayload::WriteOk => "write_ok",
+        KvPayload::Cas { .. } => "cas",
+        KvPayload::CasOk => "cas_ok",
+    };
+    KvError::Rpc(RpcError::Malformed(serde::de::Error::custom(format!(
+        "unexpected kv reply {}",
+        kind
+    ))))
+}
@@ -0,0 +1,295 @@
+//! A g-counter kept in `seq-kv` rather than in the nodes: `add` reads the
+//! counter and swaps in the sum, starting over whenever another node got
+//! there first.
+
+use crate::kv::{KvError, SeqKv};
+use crate::{Body, ErrorBody, ErrorCode, ErrorPayload, Event, Init, Message, Node, NodeId};
+use crate::{Outbox, Rpc};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::Sender;
+
+/// The one key the counter is stored under in `seq-kv`.
+const COUNTER: &str = "counter";
+
+/// How often a request may fail to reach `seq-kv` before the client is told
+/// to try again later. Lost races with other nodes do not count.
+pub const MAX_FAILURES: u32 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Add { delta: u64 },
+    AddOk,
+    Read,
+    ReadOk { value: u64 },
+}
+
+/// What became of a kv request. `KvError` itself cannot be cloned, which
+/// injected payloads must be.
+#[derive(Debug, Clone)]
+pub enum Outcome<T> {
+    Ok(T),
+    KeyDoesNotExist,
+    PreconditionFailed,
+    Failed(String),
+}
+
+impl<T> From<Result<T, KvError>> for Outcome<T> {
+    fn from(result: Result<T, KvError>) -> Self {
+        match result {
+            Ok(value) => Outcome::Ok(value),
+            Err(KvError::KeyDoesNotExist) => Outcome::KeyDoesNotExist,
+            Err(KvError::PreconditionFailed) => Outcome::PreconditionFailed,
+            Err(e) => Outcome::Failed(e.to_string()),
+        }
+    }
+}
+
+/// A client request on its way through the `add` or `read` loop.
+#[derive(Debug, Clone)]
+pub struct Pending {
+    /// What to answer the client with once the loop is done.
+    reply: Message<Payload>,
+    failures: u32,
+}
+
+/// Steps of the `add` and `read` loops.
+#[derive(Debug, Clone)]
+pub enum InjectedPayload {
+    AddRead {
+        pending: Pending,
+        delta: u64,
+        value: Outcome<u64>,
+    },
+    AddCas {
+        pending: Pending,
+        delta: u64,
+        outcome: Outcome<()>,
+    },
+    ReadBarrier {
+        pending: Pending,
+        outcome: Outcome<()>,
+    },
+    ReadValue {
+        pending: Pending,
+        value: Outcome<u64>,
+    },
+}
+
+pub struct KvCounterNode {
+    node: NodeId,
+    rpc: Rpc<Payload, InjectedPayload>,
+    kv: SeqKv<Payload, InjectedPayload>,
+    barriers: u64,
+}
+
+impl KvCounterNode {
+    fn read_for_add(
+        &self,
+        pending: Pending,
+        delta: u64,
+        outbox: &mut Outbox<InjectedPayload>,
+    ) -> anyhow::Result<()> {
+        self.kv
+            .read(COUNTER, outbox, move |value| InjectedPayload::AddRead {
+                pending,
+                delta,
+                value: value.into(),
+            })
+    }
+
+    /// `seq-kv` may serve a read from any state at least as new as the last
+    /// one this node saw. Writing first makes this node see the newest
+    /// state, so the read that follows cannot miss an acknowledged `add`.
+    fn barrier_for_read(
+        &mut self,
+        pending: Pending,
+        outbox: &mut Outbox<InjectedPayload>,
+    ) -> anyhow::Result<()> {
+        self.barriers += 1;
+        let key = format!("barrier-{}", self.node);
+        self.kv.write(key, self.barriers, outbox, move |outcome| {
+            InjectedPayload::ReadBarrier {
+                pending,
+                outcome: outcome.into(),
+            }
+        })
+    }
+
+    fn step_injected(
+        &mut self,
+        payload: InjectedPayload,
+        outbox: &mut Outbox<InjectedPayload>,
+    ) -> anyhow::Result<()> {
+        match payload {
+            InjectedPayload::AddRead {
+                pending,
+                delta,
+                value,
+            } => {
+                let current = match value {
+                    Outcome::Ok(value) => value,
+                    Outcome::KeyDoesNotExist => 0,
+                    // Nothing was written yet, so trying again is safe.
+                    Outcome::PreconditionFailed | Outcome::Failed(_) => {
+                        let Some(pending) = pending.failed(outbox, "add")? else {
+                            return Ok(());
+                        };
+                        return self.read_for_add(pending, delta, outbox);
+                    }
+                };
+                self.kv.cas(
+                    COUNTER,
+                    current,
+                    current + delta,
+                    true,
+                    outbox,
+                    move |outcome| InjectedPayload::AddCas {
+                        pending,
+                        delta,
+                        outcome: outcome.into(),
+                    },
+                )
+            }
+            InjectedPayload::AddCas {
+                pending,
+                delta,
+                outcome,
+            } => match outcome {
+                Outcome::Ok(()) => pending.answer(Payload::AddOk, outbox),
+                // Someone else's add got in between: start over from the
+                // value that beat this one.
+                Outcome::PreconditionFailed | Outcome::KeyDoesNotExist => {
+                    outbox.log(format!("lost a cas race, adding {} again", delta));
+                    self.read_for_add(pending, delta, outbox)
+                }
+                // The swap may or may not have happened, so retrying could
+                // count `delta` twice.
+                Outcome::Failed(e) => {
+                    let error = ErrorBody::new(ErrorCode::Timeout, format!("add: {}", e));
+                    pending.fail(error, outbox)
+                }
+            },
+            InjectedPayload::ReadBarrier { pending, outcome } => match outcome {
+                Outcome::Ok(()) => {
+                    self.kv
+                        .read(COUNTER, outbox, move |value| InjectedPayload::ReadValue {
+                            pending,
+                            value: value.into(),
+                        })
+                }
+                _ => {
+                    let Some(pending) = pending.failed(outbox, "read")? else {
+                        return Ok(());
+                    };
+                    self.barrier_for_read(pending, outbox)
+                }
+            },
+            InjectedPayload::ReadValue { pending, value } => {
+                let value = match value {
+                    Outcome::Ok(value) => value,
+                    Outcome::KeyDoesNotExist => 0,
+                    Outcome::PreconditionFailed | Outcome::Failed(_) => {
+                        let Some(pending) = pending.failed(outbox, "read")? else {
+                            return Ok(());
+                        };
+                        return self.barrier_for_read(pending, outbox);
+                    }
+                };
+                pending.answer(Payload::ReadOk { value }, outbox)
+            }
+        }
+    }
+}
+
+impl Pending {
+    fn new(reply: Message<Payload>) -> Self {
+        Self { reply, failures: 0 }
+    }
+
+    fn answer(
+        mut self,
+        payload: Payload,
+        outbox: &mut Outbox<InjectedPayload>,
+    ) -> anyhow::Result<()> {
+        self.reply.body.payload = payload;
+        outbox.send(&self.reply).context("reply")?;
+        Ok(())
+    }
+
+    fn fail(self, error: ErrorBody, outbox: &mut Outbox<InjectedPayload>) -> anyhow::Result<()> {
+        let failed = Message {
+            src: self.reply.src,
+            dst: self.reply.dst,
+            body: Body {
+                id: None,
+                in_reply_to: self.reply.body.in_reply_to,
+                payload: ErrorPayload::Error(error),
+            },
+        };
+        outbox.send(&failed).context("error reply")?;
+        Ok(())
+    }
+
+    /// Counts a failure to reach `seq-kv` that left nothing changed. Gives
+    /// the request back to be tried again, or answers it with
+    /// `temporarily-unavailable` once it failed too often.
+    fn failed(
+        mut self,
+        outbox: &mut Outbox<InjectedPayload>,
+        what: &str,
+    ) -> anyhow::Result<Option<Self>> {
+        self.failures += 1;
+        if self.failures < MAX_FAILURES {
+            return Ok(Some(self));
+        }
+        let error = ErrorBody::new(
+            ErrorCode::TemporarilyUnavailable,
+            format!("{}: seq-kv failed {} times", what, self.failures),
+        );
+        self.fail(error, outbox)?;
+        Ok(None)
+    }
+}
+
+impl Node<(), Payload, InjectedPayload> for KvCounterNode {
+    fn from_init(
+        _state: (),
+        init: Init,
+        tx: Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self> {
+        let rpc = Rpc::new(tx);
+        Ok(Self {
+            node: init.node_id,
+            kv: SeqKv::new(init.node_id, rpc.clone()),
+            rpc,
+            barriers: 0,
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        outbox: &mut Outbox<InjectedPayload>,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => Ok(()),
+            Event::Injected(payload) => self.step_injected(payload, outbox),
+            Event::Message(input) => {
+                let reply = input.into_reply();
+                match reply.body.payload {
+                    Payload::Add { delta: 0 } => Pending::new(reply).answer(Payload::AddOk, outbox),
+                    Payload::Add { delta } => self.read_for_add(Pending::new(reply), delta, outbox),
+                    Payload::Read => self.barrier_for_read(Pending::new(reply), outbox),
+                    Payload::AddOk | Payload::ReadOk { .. } => Ok(()),
+                }
+            }
+        }
+    }
+
+    fn rpc(&self) -> Option<Rpc<Payload, InjectedPayload>> {
+        Some(self.rpc.clone())
+    }
+}
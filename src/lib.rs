@@ -1,34 +1,74 @@
 use anyhow::Context;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::io::{BufRead, StdoutLock, Write};
-use std::sync::mpsc::{channel, Sender};
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
 use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "async")]
+mod async_runtime;
+mod cluster;
+pub mod counter;
+pub mod crdt;
+mod envelope;
+mod error;
+pub mod faults;
+pub mod gossip;
+mod handshake;
+pub mod kv;
+pub mod kv_counter;
+pub mod middleware;
+mod node_id;
+mod outbox;
+mod output;
+mod router;
+mod rpc;
+mod scheduler;
+pub mod services;
+pub mod sim;
+mod transport;
+pub mod workload;
+#[cfg(feature = "async")]
+pub use async_runtime::*;
+pub use cluster::*;
+pub use envelope::*;
+pub use error::*;
+use handshake::Handshake;
+pub use handshake::{InitError, EARLY_LIMIT};
+use middleware::{Identity, Layer, NodeService, Service};
+pub use node_id::*;
+pub use outbox::*;
+pub use output::*;
+pub use router::*;
+pub use rpc::*;
+use scheduler::Wake;
+pub use scheduler::*;
+use transport::Endpoint;
+pub use transport::*;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message<Payload> {
-    pub src: String,
+    pub src: NodeId,
     #[serde(rename = "dest")]
-    pub dst: String,
+    pub dst: NodeId,
     pub body: Body<Payload>,
 }
 
 impl<Payload> Message<Payload> {
-    pub fn into_reply(self, id: Option<&mut usize>) -> Self {
+    /// Turns a request around. The reply's `msg_id` is assigned by the
+    /// [`Outbox`] it is sent through.
+    pub fn into_reply(self) -> Self {
         Self {
             src: self.dst,
             dst: self.src,
             body: Body {
-                id: id.map(|id| {
-                    let mid = *id;
-                    *id += 1;
-                    mid
-                }),
+                id: None,
                 in_reply_to: self.body.id,
                 payload: self.body.payload,
             },
         }
     }
-    pub fn send(&self, output: &mut impl Write) -> anyhow::Result<()>
+    pub fn send(&self, output: &mut (impl Write + ?Sized)) -> anyhow::Result<()>
     where
         Payload: Serialize,
     {
@@ -63,8 +103,8 @@ enum InitPayload {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Init {
-    pub node_id: String,
-    pub node_ids: Vec<String>,
+    pub node_id: NodeId,
+    pub node_ids: Vec<NodeId>,
 }
 
 pub trait Node<S, Payload, InjectedPayload = ()> {
@@ -79,71 +119,411 @@ pub trait Node<S, Payload, InjectedPayload = ()> {
     fn step(
         &mut self,
         input: Event<Payload, InjectedPayload>,
-        output: &mut StdoutLock,
+        outbox: &mut Outbox<InjectedPayload>,
     ) -> anyhow::Result<()>;
+
+    /// Events the runtime should inject every `Duration`, in place of timer
+    /// threads spawned by the node itself, so that a simulator can drive them
+    /// from its own clock.
+    fn periodic(&self) -> Vec<(Duration, InjectedPayload)> {
+        Vec::new()
+    }
+
+    /// Nodes that issue requests with [`Rpc::call`] return their handle here so
+    /// that `main_loop` can route replies to the waiting continuations.
+    fn rpc(&self) -> Option<Rpc<Payload, InjectedPayload>> {
+        None
+    }
+}
+
+/// How many inputs the main loop takes on at most before its output goes out.
+const MAX_BATCH_INPUTS: usize = 256;
+
+/// What the main loop waits on: events for the node, or answers the runtime
+/// gives on the node's behalf.
+enum Input<P, IP> {
+    Event(Event<P, IP>),
+    Reject(Message<ErrorPayload>, String),
 }
 
+/// Runs `N` against Maelstrom over stdin and stdout.
+///
+/// Input that does not decode is logged and skipped, see [`decode`]. Set
+/// `RUSTENGAN_STRICT=1` to make it fatal instead, which is what tests want.
+/// Set `RUSTENGAN_STATS=1` to have the [`OutputStats`] logged on exit.
 pub fn main_loop<S, N, P, IP>(init_state: S) -> anyhow::Result<()>
 where
     P: DeserializeOwned + Send + 'static,
     N: Node<S, P, IP>,
-    IP: Send + 'static,
+    IP: Clone + Send + 'static,
+{
+    main_loop_with::<S, N, P, IP, _>(init_state, Identity)
+}
+
+/// Like [`main_loop`], but with `layers` around the node, see
+/// [`middleware`].
+///
+/// Both run over the transport that `RUSTENGAN_TRANSPORT` names: `stdio`,
+/// the default, or `tcp:HOST:PORT` or `unix:PATH` to connect to.
+pub fn main_loop_with<S, N, P, IP, L>(init_state: S, layers: L) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    N: Node<S, P, IP>,
+    IP: Clone + Send + 'static,
+    L: Layer<NodeService<N, S>>,
+    L::Service: Service<P, IP>,
+{
+    match Endpoint::from_env()? {
+        Endpoint::Stdio => main_loop_on::<S, N, P, IP, L, _>(Stdio, init_state, layers),
+        Endpoint::Tcp(addr) => {
+            let transport =
+                TcpTransport::connect(&addr).with_context(|| format!("connect to {}", addr))?;
+            main_loop_on::<S, N, P, IP, L, _>(transport, init_state, layers)
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            let transport = UnixTransport::connect(&path)
+                .with_context(|| format!("connect to {}", path.display()))?;
+            main_loop_on::<S, N, P, IP, L, _>(transport, init_state, layers)
+        }
+    }
+}
+
+/// Like [`main_loop_with`], over `transport`.
+pub fn main_loop_on<S, N, P, IP, L, T>(transport: T, init_state: S, layers: L) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    N: Node<S, P, IP>,
+    IP: Clone + Send + 'static,
+    L: Layer<NodeService<N, S>>,
+    L::Service: Service<P, IP>,
+    T: Transport,
 {
+    let strict = strict_from_env();
     let (tx, rx) = channel();
+    let (inject, injected) = channel();
 
-    let stdin = std::io::stdin().lock();
-    let mut stdin = stdin.lines();
-    let mut stdout = std::io::stdout().lock();
+    let (mut input, output) = transport.split().context("open transport")?;
+    let mut output = BatchWriter::new(output);
 
-    let init_msg: Message<InitPayload> = serde_json::from_str(
-        &stdin
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    let mut scheduler = Scheduler::new(SystemClock::new(), seed);
+    let mut outbox = Outbox::new();
+    let mut handshake = Handshake::default();
+    let init_msg = loop {
+        let line = (&mut input)
+            .lines()
             .next()
-            .expect("no init message received")
-            .context("failed to read init message from stdin")?,
-    )
-    .context("init message could not be deserialized")?;
-    let InitPayload::Init(init) = init_msg.body.payload else {
-        panic!("first message should be init");
-    };
-    let mut node: N =
-        Node::from_init(init_state, init, tx.clone()).context("node initilization failed")?;
-
-    let reply = Message {
-        src: init_msg.dst,
-        dst: init_msg.src,
-        body: Body {
-            id: Some(0),
-            in_reply_to: init_msg.body.id,
-            payload: InitPayload::InitOk,
-        },
+            .ok_or(InitError::Eof)?
+            .context("failed to read init message")?;
+        let offered = handshake.offer(line, &mut outbox, strict);
+        flush(&mut outbox, &mut output, &mut scheduler)?;
+        output.flush().context("flush output")?;
+        if let Some(init_msg) = offered.context("init handshake failed")? {
+            break init_msg;
+        }
     };
-    serde_json::to_writer(&mut stdout, &reply).context("serialize response to init")?;
-    stdout.write_all(b"\n").context("write trailing newline")?;
+    let node: N = Node::from_init(init_state, init_msg.body.payload.clone(), inject)
+        .context("node initilization failed")?;
+    outbox
+        .reply(&init_msg, InitPayload::InitOk)
+        .context("serialize response to init")?;
+    flush(&mut outbox, &mut output, &mut scheduler)?;
 
-    drop(stdin);
-    let jh = thread::spawn(move || {
-        let stdin = std::io::stdin().lock();
-        for line in stdin.lines() {
-            let line = line.context("Maelstrom input from STDIN could not be read")?;
-            let input: Message<P> = serde_json::from_str(&line)
-                .context("Maelstrom input from STDIN could not be deserialized")?;
-            if let Err(_) = tx.send(Event::Message(input)) {
-                return Ok::<_, anyhow::Error>(());
+    let forward_tx = tx.clone();
+    thread::spawn(move || {
+        for event in injected {
+            if forward_tx.send(Input::Event(event)).is_err() {
+                break;
             }
         }
-        let _ = tx.send(Event::EOF);
-        Ok(())
+    });
+    arm_periodic(&node, &mut outbox);
+    flush(&mut outbox, &mut output, &mut scheduler)?;
+    output.flush().context("flush output")?;
+    let rpc = node.rpc();
+    let mut service = layers.layer(NodeService::new(node));
+    let input_tx = tx.clone();
+    let input_rpc = rpc.clone();
+    let jh = thread::spawn(move || {
+        let (tx, rpc) = (input_tx, input_rpc);
+        let early = handshake.into_early().into_iter().map(Ok);
+        let result = (|| {
+            for line in early.chain(input.lines()) {
+                let line = line.context("Maelstrom input could not be read")?;
+                let input = match decode(&line, rpc.as_ref(), strict)? {
+                    Decoded::Message(input) => Input::Event(Event::Message(input)),
+                    Decoded::Resolved => continue,
+                    Decoded::Dropped(reason) => {
+                        eprintln!("{}", reason);
+                        continue;
+                    }
+                    Decoded::Rejected(reply, reason) => Input::Reject(reply, reason),
+                };
+                if tx.send(input).is_err() {
+                    break;
+                }
+            }
+            Ok::<_, anyhow::Error>(())
+        })();
+        // Also on error, or the main loop would wait for input forever.
+        let _ = tx.send(Input::Event(Event::EOF));
+        result
     });
 
-    for input in rx {
-        if let Event::EOF = input {
+    loop {
+        let input = match scheduler.until_next() {
+            Some(wait) => match rx.recv_timeout(wait) {
+                Ok(input) => Some(input),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(input) => Some(input),
+                Err(_) => break,
+            },
+        };
+        // Whatever else is already waiting is handled before the output
+        // goes out, so that a busy node writes one batch per burst.
+        let inputs = input
+            .into_iter()
+            .chain(rx.try_iter().take(MAX_BATCH_INPUTS - 1));
+        // Rejects are answered in turn with the events around them, so that
+        // replies go out in the order their requests came in.
+        let mut batch = Vec::new();
+        let mut eof = false;
+        for input in inputs {
+            match input {
+                Input::Event(Event::EOF) => {
+                    eof = true;
+                    break;
+                }
+                input => batch.push(input),
+            }
+        }
+        for wake in scheduler.expired() {
+            batch.extend(
+                wake.fire(rpc.as_ref())
+                    .map(|event| Input::Event(Event::Injected(event))),
+            );
+        }
+        for input in batch {
+            let input = match input {
+                Input::Event(input) => input,
+                Input::Reject(reply, reason) => {
+                    outbox.log(reason);
+                    outbox.send(&reply)?;
+                    continue;
+                }
+            };
+            let origin = Origin::of(&input);
+            if let Err(e) = service.call(input, &mut outbox) {
+                let Some(reply) = origin.error_reply(&e) else {
+                    return Err(e).context("Node step function failed");
+                };
+                outbox.log(format!("replying with error to {}: {:#}", reply.dst, e));
+                outbox.send(&reply)?;
+            }
+            flush(&mut outbox, &mut output, &mut scheduler)?;
+        }
+        flush(&mut outbox, &mut output, &mut scheduler)?;
+        output.flush().context("flush output")?;
+        if eof {
             break;
         }
-        node.step(input, &mut stdout)
-            .context("Node step function failed")?;
     }
     jh.join()
-        .expect("stdin thread panic")
-        .context("stdin thread err")?;
+        .expect("input thread panic")
+        .context("input thread err")?;
+    if stats_from_env() {
+        eprintln!("output: {}", output.stats());
+    }
     Ok(())
 }
+
+/// Whether `RUSTENGAN_STATS` asks for output statistics on exit.
+fn stats_from_env() -> bool {
+    std::env::var_os("RUSTENGAN_STATS").is_some_and(|v| v != "0")
+}
+
+/// Whether `RUSTENGAN_STRICT` asks for undecodable input to be fatal.
+pub(crate) fn strict_from_env() -> bool {
+    std::env::var_os("RUSTENGAN_STRICT").is_some_and(|v| v != "0")
+}
+
+/// Carries out the effects collected in `outbox` since the last flush.
+fn flush<IP: Clone>(
+    outbox: &mut Outbox<IP>,
+    output: &mut impl Write,
+    scheduler: &mut Scheduler<Wake<IP>>,
+) -> anyhow::Result<()> {
+    for effect in outbox.take_effects() {
+        match scheduler.apply(effect) {
+            Some(Effect::Send(msg)) => msg.send(output).context("send message")?,
+            Some(Effect::Log(line)) => eprintln!("{}", line),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Registers the node's [`Node::periodic`] events as timers, each with a
+/// tenth of its interval as jitter so that nodes do not tick in lockstep.
+pub(crate) fn arm_periodic<S, N, P, IP>(node: &N, outbox: &mut Outbox<IP>)
+where
+    N: Node<S, P, IP>,
+{
+    for (interval, event) in node.periodic() {
+        outbox.every(interval, interval / 10, event);
+    }
+}
+
+/// What became of one incoming line.
+pub(crate) enum Decoded<P> {
+    Message(Message<P>),
+    /// The line was a reply and has been handed to a pending [`Rpc`] call.
+    Resolved,
+    /// The line was dropped, for the logged reason.
+    Dropped(String),
+    /// The line was a request the node cannot handle; answer it with this.
+    Rejected(Message<ErrorPayload>, String),
+}
+
+/// Parses one incoming line, first offering it to `rpc` as a reply.
+///
+/// Only the [`Envelope`] is read up front; the body is deserialized into
+/// `P` once the line turns out to be for the node.
+///
+/// Broken JSON is dropped, and so is a message whose body does not fit `P`,
+/// though requests are answered first: with `not-supported` if `P` has no
+/// variant for their `type`, with `malformed-request` if it does but the
+/// body is wrong. In `strict` mode both are errors instead, except for late
+/// replies to calls that already timed out. A second `init` is always
+/// answered with `malformed-request`.
+pub(crate) fn decode<P, IP>(
+    line: &str,
+    rpc: Option<&Rpc<P, IP>>,
+    strict: bool,
+) -> anyhow::Result<Decoded<P>>
+where
+    P: DeserializeOwned + Send + 'static,
+    IP: Send + 'static,
+{
+    let envelope = match Envelope::parse(line) {
+        Ok(envelope) => envelope,
+        Err(e) if strict => return Err(e).context("input could not be deserialized"),
+        Err(e) => return Ok(Decoded::Dropped(format!("dropping malformed input: {}", e))),
+    };
+    if let (Some(rpc), Some(id)) = (rpc, envelope.in_reply_to) {
+        // Only a reply somebody waits for is worth parsing as a whole.
+        if rpc.awaits(id) {
+            let reply = envelope
+                .to_value()
+                .context("reply could not be deserialized")?;
+            if rpc.resolve(reply).is_ok() {
+                return Ok(Decoded::Resolved);
+            }
+        }
+    }
+    let kind = envelope.kind();
+    if kind == "init" {
+        let reason = format!("rejecting another init from {}", envelope.src);
+        let error = ErrorBody::new(ErrorCode::MalformedRequest, "node is already initialized");
+        return Ok(match error_reply(&envelope.with_payload(()), error) {
+            Some(reply) => Decoded::Rejected(reply, reason),
+            None => Decoded::Dropped(reason),
+        });
+    }
+    let payload = match envelope.payload() {
+        Ok(payload) => payload,
+        Err(e) => {
+            // Replies that arrive after their call timed out are expected.
+            if envelope.in_reply_to.is_some() {
+                let reason = format!("dropping {} from {}: {}", kind, envelope.src, e);
+                return Ok(Decoded::Dropped(reason));
+            }
+            if strict {
+                return Err(e)
+                    .with_context(|| format!("unsupported message from {}", envelope.src));
+            }
+            let error = if is_unknown_type(&e) {
+                ErrorBody::new(
+                    ErrorCode::NotSupported,
+                    format!("{} is not supported: {}", kind, e),
+                )
+            } else {
+                ErrorBody::new(ErrorCode::MalformedRequest, format!("bad {}: {}", kind, e))
+            };
+            return Ok(match error_reply(&envelope.with_payload(()), error) {
+                Some(reply) => {
+                    let reason = format!("rejecting {} from {}: {}", kind, envelope.src, e);
+                    Decoded::Rejected(reply, reason)
+                }
+                None => Decoded::Dropped(format!("dropping {} from {}: {}", kind, envelope.src, e)),
+            });
+        }
+    };
+    Ok(Decoded::Message(envelope.with_payload(payload)))
+}
+
+/// Whether `e` is serde turning down a `type` the payload enum has no
+/// variant for, rather than a body that is wrong for a type it knows.
+/// serde only says so in the message.
+fn is_unknown_type(e: &serde_json::Error) -> bool {
+    e.is_data() && e.to_string().starts_with("unknown variant")
+}
+
+/// Answers `request` with `error`, unless it is itself a reply or cannot be
+/// replied to for lack of a `msg_id`.
+pub(crate) fn error_reply<P>(
+    request: &Message<P>,
+    error: ErrorBody,
+) -> Option<Message<ErrorPayload>> {
+    let (Some(id), None) = (request.body.id, request.body.in_reply_to) else {
+        return None;
+    };
+    Some(Message {
+        src: request.dst,
+        dst: request.src,
+        body: Body {
+            id: None,
+            in_reply_to: Some(id),
+            payload: ErrorPayload::Error(error),
+        },
+    })
+}
+
+/// Who to answer if handling a request fails with an [`ErrorBody`].
+pub(crate) struct Origin(Option<(NodeId, NodeId, usize)>);
+
+impl Origin {
+    pub(crate) fn of<P, IP>(input: &Event<P, IP>) -> Self {
+        match input {
+            Event::Message(msg) => Self::of_message(msg),
+            _ => Origin(None),
+        }
+    }
+
+    pub(crate) fn of_message<P>(msg: &Message<P>) -> Self {
+        match msg.body.in_reply_to {
+            None => Origin(msg.body.id.map(|id| (msg.src, msg.dst, id))),
+            Some(_) => Origin(None),
+        }
+    }
+
+    pub(crate) fn error_reply(self, e: &anyhow::Error) -> Option<Message<ErrorPayload>> {
+        let (src, dst, id) = self.0?;
+        let error = e.downcast_ref::<ErrorBody>()?;
+        Some(Message {
+            src: dst,
+            dst: src,
+            body: Body {
+                id: None,
+                in_reply_to: Some(id),
+                payload: ErrorPayload::Error(error.clone()),
+            },
+        })
+    }
+}
@@ -0,0 +1,458 @@
+//! Reusable layers around [`Node::step`], in the spirit of `tower`.
+//!
+//! A [`Service`] is anything that handles one [`Event`] at a time, the node
+//! itself at the bottom. A [`Layer`] wraps a service in another one, and
+//! [`Layers`] stacks several so that `main_loop_with` can put them around any
+//! node without touching it.
+
+use crate::{Effect, ErrorBody, ErrorCode, Event, Message, Node, NodeId, Outbox};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub trait Service<Payload, InjectedPayload = ()> {
+    fn call(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        outbox: &mut Outbox<InjectedPayload>,
+    ) -> anyhow::Result<()>;
+}
+
+impl<P, IP, F> Service<P, IP> for F
+where
+    F: FnMut(Event<P, IP>, &mut Outbox<IP>) -> anyhow::Result<()>,
+{
+    fn call(&mut self, input: Event<P, IP>, outbox: &mut Outbox<IP>) -> anyhow::Result<()> {
+        self(input, outbox)
+    }
+}
+
+pub trait Layer<S> {
+    type Service;
+
+    fn layer(&self, inner: S) -> Self::Service;
+}
+
+/// The innermost service: hands every event to the node.
+pub struct NodeService<N, S> {
+    node: N,
+    state: PhantomData<S>,
+}
+
+impl<N, S> NodeService<N, S> {
+    pub fn new(node: N) -> Self {
+        Self {
+            node,
+            state: PhantomData,
+        }
+    }
+}
+
+impl<S, P, IP, N: Node<S, P, IP>> Service<P, IP> for NodeService<N, S> {
+    fn call(&mut self, input: Event<P, IP>, outbox: &mut Outbox<IP>) -> anyhow::Result<()> {
+        self.node.step(input, outbox)
+    }
+}
+
+/// Leaves the service as it is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Identity;
+
+impl<S> Layer<S> for Identity {
+    type Service = S;
+
+    fn layer(&self, inner: S) -> S {
+        inner
+    }
+}
+
+/// `Outer` wrapped around `Inner`.
+#[derive(Debug, Clone)]
+pub struct Stack<Inner, Outer> {
+    inner: Inner,
+    outer: Outer,
+}
+
+impl<S, Inner: Layer<S>, Outer: Layer<Inner::Service>> Layer<S> for Stack<Inner, Outer> {
+    type Service = Outer::Service;
+
+    fn layer(&self, service: S) -> Self::Service {
+        self.outer.layer(self.inner.layer(service))
+    }
+}
+
+/// Builds a stack of layers. The first one added sees events first.
+#[derive(Debug, Clone)]
+pub struct Layers<L>(L);
+
+impl Layers<Identity> {
+    pub fn new() -> Self {
+        Layers(Identity)
+    }
+}
+
+impl Default for Layers<Identity> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L> Layers<L> {
+    pub fn layer<T>(self, layer: T) -> Layers<Stack<T, L>> {
+        Layers(Stack {
+            inner: layer,
+            outer: self.0,
+        })
+    }
+}
+
+impl<S, L: Layer<S>> Layer<S> for Layers<L> {
+    type Service = L::Service;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        self.0.layer(inner)
+    }
+}
+
+/// The `type` of a message, or what else the event is.
+fn kind_of<P: Serialize, IP>(input: &Event<P, IP>) -> String {
+    match input {
+        Event::Message(msg) => serde_json::to_value(&msg.body.payload)
+            .ok()
+            .and_then(|v| v.get("type")?.as_str().map(str::to_string))
+            .unwrap_or_else(|| "untyped".to_string()),
+        Event::Injected(_) => "injected".to_string(),
+        Event::EOF => "eof".to_string(),
+    }
+}
+
+/// The `(src, msg_id)` of a request, that is a message that is not a reply.
+fn request_id<P, IP>(input: &Event<P, IP>) -> Option<(NodeId, usize)> {
+    match input {
+        Event::Message(msg) if msg.body.in_reply_to.is_none() => {
+            msg.body.id.map(|id| (msg.src, id))
+        }
+        _ => None,
+    }
+}
+
+/// Logs every message handled and every failure.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogLayer;
+
+impl<S> Layer<S> for LogLayer {
+    type Service = Log<S>;
+
+    fn layer(&self, inner: S) -> Log<S> {
+        Log { inner }
+    }
+}
+
+pub struct Log<S> {
+    inner: S,
+}
+
+impl<S, P, IP> Service<P, IP> for Log<S>
+where
+    S: Service<P, IP>,
+    P: Serialize,
+{
+    fn call(&mut self, input: Event<P, IP>, outbox: &mut Outbox<IP>) -> anyhow::Result<()> {
+        let kind = kind_of(&input);
+        let from = match &input {
+            Event::Message(msg) => format!(" from {}", msg.src),
+            _ => String::new(),
+        };
+        if let Event::Message(_) = input {
+            outbox.log(format!("handling {}{}", kind, from));
+        }
+        let result = self.inner.call(input, outbox);
+        if let Err(e) = &result {
+            outbox.log(format!("{}{} failed: {:#}", kind, from, e));
+        }
+        result
+    }
+}
+
+/// What [`MetricsLayer`] knows about one type of event.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TypeMetrics {
+    pub handled: usize,
+    pub failed: usize,
+    pub busy: Duration,
+}
+
+/// Counts handled and failed events and the time spent on them per `type`.
+/// Clones share their counts, so keep one to read them.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsLayer {
+    metrics: Arc<Mutex<BTreeMap<String, TypeMetrics>>>,
+}
+
+impl MetricsLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, TypeMetrics> {
+        self.metrics.lock().unwrap().clone()
+    }
+}
+
+impl fmt::Display for MetricsLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (kind, m) in self.snapshot() {
+            writeln!(
+                f,
+                "{}: {} handled, {} failed, {:?} busy",
+                kind, m.handled, m.failed, m.busy
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = Metrics<S>;
+
+    fn layer(&self, inner: S) -> Metrics<S> {
+        Metrics {
+            inner,
+            metrics: Arc::clone(&self.metrics),
+        }
+    }
+}
+
+pub struct Metrics<S> {
+    inner: S,
+    metrics: Arc<Mutex<BTreeMap<String, TypeMetrics>>>,
+}
+
+impl<S, P, IP> Service<P, IP> for Metrics<S>
+where
+    S: Service<P, IP>,
+    P: Serialize,
+{
+    fn call(&mut self, input: Event<P, IP>, outbox: &mut Outbox<IP>) -> anyhow::Result<()> {
+        let kind = kind_of(&input);
+        let start = Instant::now();
+        let result = self.inner.call(input, outbox);
+        let mut metrics = self.metrics.lock().unwrap();
+        let m = metrics.entry(kind).or_default();
+        m.handled += 1;
+        m.failed += usize::from(result.is_err());
+        m.busy += start.elapsed();
+        result
+    }
+}
+
+/// Handles every request only once. A repeated request (same `src` and
+/// `msg_id`) gets the replies the first one got sent again instead, or
+/// nothing if those are still outstanding.
+///
+/// Replies are recorded whenever they go out, so a request that is answered
+/// later, from an rpc continuation or a timer, is replayed as well.
+#[derive(Debug, Clone, Copy)]
+pub struct DedupLayer {
+    capacity: usize,
+}
+
+impl DedupLayer {
+    /// Remembers the last `capacity` requests.
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity }
+    }
+}
+
+impl Default for DedupLayer {
+    fn default() -> Self {
+        Self::new(10_000)
+    }
+}
+
+impl<S> Layer<S> for DedupLayer {
+    type Service = Dedup<S>;
+
+    fn layer(&self, inner: S) -> Dedup<S> {
+        Dedup {
+            inner,
+            capacity: self.capacity,
+            order: VecDeque::new(),
+            replies: HashMap::new(),
+        }
+    }
+}
+
+pub struct Dedup<S> {
+    inner: S,
+    capacity: usize,
+    order: VecDeque<(NodeId, usize)>,
+    replies: HashMap<(NodeId, usize), Vec<Message<serde_json::Value>>>,
+}
+
+impl<S> Dedup<S> {
+    /// Adds the replies to remembered requests among the effects queued
+    /// since `before`.
+    fn record<IP>(&mut self, outbox: &Outbox<IP>, before: usize) {
+        for effect in &outbox.effects()[before..] {
+            let Effect::Send(msg) = effect else {
+                continue;
+            };
+            let Some(id) = msg.body.in_reply_to else {
+                continue;
+            };
+            if let Some(replies) = self.replies.get_mut(&(msg.dst, id)) {
+                replies.push(msg.clone());
+            }
+        }
+    }
+}
+
+impl<S, P, IP> Service<P, IP> for Dedup<S>
+where
+    S: Service<P, IP>,
+{
+    fn call(&mut self, input: Event<P, IP>, outbox: &mut Outbox<IP>) -> anyhow::Result<()> {
+        let before = outbox.effects().len();
+        let Some(key) = request_id(&input) else {
+            let result = self.inner.call(input, outbox);
+            self.record(outbox, before);
+            return result;
+        };
+        if let Some(replies) = self.replies.get(&key) {
+            outbox.log(format!("{} repeated request {}", key.0, key.1));
+            for reply in replies {
+                outbox.send(reply)?;
+            }
+            return Ok(());
+        }
+        self.inner.call(input, outbox)?;
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.replies.remove(&oldest);
+            }
+        }
+        self.order.push_back(key);
+        self.replies.insert(key, Vec::new());
+        self.record(outbox, before);
+        Ok(())
+    }
+}
+
+/// Turns a panic in the node into a `crash` error reply (for requests) or a
+/// log line (for everything else), rather than taking the process down.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CatchPanicLayer;
+
+impl<S> Layer<S> for CatchPanicLayer {
+    type Service = CatchPanic<S>;
+
+    fn layer(&self, inner: S) -> CatchPanic<S> {
+        CatchPanic { inner }
+    }
+}
+
+pub struct CatchPanic<S> {
+    inner: S,
+}
+
+impl<S, P, IP> Service<P, IP> for CatchPanic<S>
+where
+    S: Service<P, IP>,
+{
+    fn call(&mut self, input: Event<P, IP>, outbox: &mut Outbox<IP>) -> anyhow::Result<()> {
+        let is_request = request_id(&input).is_some();
+        let caught = panic::catch_unwind(AssertUnwindSafe(|| self.inner.call(input, outbox)));
+        let Err(panic) = caught else {
+            return caught.unwrap();
+        };
+        let what = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        if is_request {
+            return Err(
+                ErrorBody::new(ErrorCode::Crash, format!("node panicked: {}", what)).into(),
+            );
+        }
+        outbox.log(format!("node panicked: {}", what));
+        Ok(())
+    }
+}
+
+/// Lets through at most `rate` client requests per second on average, in
+/// bursts of up to `burst`, and answers the rest with
+/// `temporarily-unavailable`. Traffic between servers is never limited.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitLayer {
+    rate: f64,
+    burst: f64,
+}
+
+impl RateLimitLayer {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            rate,
+            burst: f64::from(burst.max(1)),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> RateLimit<S> {
+        RateLimit {
+            inner,
+            rate: self.rate,
+            burst: self.burst,
+            tokens: self.burst,
+            refilled: Instant::now(),
+            limited: HashSet::new(),
+        }
+    }
+}
+
+pub struct RateLimit<S> {
+    inner: S,
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+    /// Clients that were turned away since the last request let through, so
+    /// that a storm is logged once per client.
+    limited: HashSet<NodeId>,
+}
+
+impl<S, P, IP> Service<P, IP> for RateLimit<S>
+where
+    S: Service<P, IP>,
+{
+    fn call(&mut self, input: Event<P, IP>, outbox: &mut Outbox<IP>) -> anyhow::Result<()> {
+        let Some((src, _)) = request_id(&input).filter(|(src, _)| src.is_client()) else {
+            return self.inner.call(input, outbox);
+        };
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.burst);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            if self.limited.insert(src) {
+                outbox.log(format!("rate limiting {}", src));
+            }
+            return Err(ErrorBody::new(
+                ErrorCode::TemporarilyUnavailable,
+                "too many requests, try again later",
+            )
+            .into());
+        }
+        self.tokens -= 1.0;
+        self.limited.clear();
+        self.inner.call(input, outbox)
+    }
+}
@@ -0,0 +1,161 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+
+/// The name of a node, client or service, as found in `src` and `dest`.
+///
+/// Server nodes (`n0`, `n1`, ...) and clients (`c0`, ...) are kept as their
+/// number, so copying, comparing and hashing them costs next to nothing.
+/// Other names, like `seq-kv`, are interned once for the life of the
+/// process. On the wire a `NodeId` is the plain string.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(Repr);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Repr {
+    Server(u32),
+    Client(u32),
+    Named(&'static str),
+}
+
+impl NodeId {
+    pub const fn server(n: u32) -> Self {
+        NodeId(Repr::Server(n))
+    }
+
+    pub const fn client(n: u32) -> Self {
+        NodeId(Repr::Client(n))
+    }
+
+    /// Whether this is one of the nodes under test, `nN`.
+    pub fn is_server(&self) -> bool {
+        matches!(self.0, Repr::Server(_))
+    }
+
+    /// Whether this is a Maelstrom client, `cN`.
+    pub fn is_client(&self) -> bool {
+        matches!(self.0, Repr::Client(_))
+    }
+
+    /// The `N` of `nN`.
+    pub fn server_index(&self) -> Option<u32> {
+        match self.0 {
+            Repr::Server(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn intern(name: &str) -> &'static str {
+        static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+        let mut names = NAMES.get_or_init(Default::default).lock().unwrap();
+        if let Some(name) = names.get(name) {
+            return name;
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        names.insert(name);
+        name
+    }
+}
+
+/// `digits` as a number, if that is how the number would be written.
+fn number(digits: &str) -> Option<u32> {
+    if digits.is_empty() || (digits.len() > 1 && digits.starts_with('0')) {
+        return None;
+    }
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// `name` as a server or client id, if it is one.
+fn numbered(name: &str) -> Option<NodeId> {
+    if let Some(n) = name.strip_prefix('n').and_then(number) {
+        Some(NodeId::server(n))
+    } else {
+        name.strip_prefix('c').and_then(number).map(NodeId::client)
+    }
+}
+
+impl From<&str> for NodeId {
+    fn from(name: &str) -> Self {
+        numbered(name).unwrap_or_else(|| NodeId(Repr::Named(NodeId::intern(name))))
+    }
+}
+
+impl From<String> for NodeId {
+    fn from(name: String) -> Self {
+        NodeId::from(name.as_str())
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = std::convert::Infallible;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(NodeId::from(name))
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Repr::Server(n) => write!(f, "n{}", n),
+            Repr::Client(n) => write!(f, "c{}", n),
+            Repr::Named(name) => f.write_str(name),
+        }
+    }
+}
+
+impl fmt::Debug for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl PartialEq<str> for NodeId {
+    fn eq(&self, other: &str) -> bool {
+        match self.0 {
+            Repr::Named(name) => name == other,
+            _ => numbered(other) == Some(*self),
+        }
+    }
+}
+
+impl PartialEq<&str> for NodeId {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+impl Serialize for NodeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Repr::Named(name) => serializer.serialize_str(name),
+            _ => serializer.collect_str(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NodeIdVisitor;
+
+        impl Visitor<'_> for NodeIdVisitor {
+            type Value = NodeId;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a node id")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<NodeId, E> {
+                Ok(NodeId::from(name))
+            }
+        }
+
+        deserializer.deserialize_str(NodeIdVisitor)
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{Body, Message, TimerId};
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+
+/// Something a node wants done as a result of handling an event. Nodes never
+/// perform I/O themselves; whatever drives them (`main_loop`, the simulator,
+/// a test) carries these out.
+#[derive(Debug, Clone)]
+pub enum Effect<InjectedPayload = ()> {
+    Send(Message<Value>),
+    /// Inject `event` after `after`, and then `every` period if given, each
+    /// time up to `jitter` later.
+    Timer {
+        id: TimerId,
+        after: Duration,
+        every: Option<Duration>,
+        jitter: Duration,
+        event: InjectedPayload,
+    },
+    Cancel(TimerId),
+    /// Give up on the [`Rpc`](crate::Rpc) call with `msg_id` `id` after `after`.
+    RpcTimeout {
+        timer: TimerId,
+        id: usize,
+        after: Duration,
+    },
+    Log(String),
+}
+
+/// Handed to `Node::step` to collect the effects of one step. The runtime
+/// keeps one per node for its whole life, so it is also what hands out
+/// `msg_id`s: every message sent through it gets the next one. The same goes
+/// for timer handles.
+#[derive(Debug)]
+pub struct Outbox<InjectedPayload = ()> {
+    effects: Vec<Effect<InjectedPayload>>,
+    next_id: usize,
+    next_timer: u64,
+}
+
+impl<InjectedPayload> Default for Outbox<InjectedPayload> {
+    fn default() -> Self {
+        Self {
+            effects: Vec::new(),
+            next_id: 0,
+            next_timer: 0,
+        }
+    }
+}
+
+impl<InjectedPayload> Outbox<InjectedPayload> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `msg` under a freshly allocated `msg_id`, which is returned.
+    /// Whatever `msg.body.id` was set to is ignored.
+    pub fn send<P: Serialize>(&mut self, msg: &Message<P>) -> anyhow::Result<usize> {
+        let payload = serde_json::to_value(&msg.body.payload)
+            .with_context(|| format!("serialize message to {}", msg.dst))?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.effects.push(Effect::Send(Message {
+            src: msg.src,
+            dst: msg.dst,
+            body: Body {
+                id: Some(id),
+                in_reply_to: msg.body.in_reply_to,
+                payload,
+            },
+        }));
+        Ok(id)
+    }
+
+    pub fn reply<Q, P: Serialize>(
+        &mut self,
+        request: &Message<Q>,
+        payload: P,
+    ) -> anyhow::Result<usize> {
+        self.send(&Message {
+            src: request.dst,
+            dst: request.src,
+            body: Body {
+                id: None,
+                in_reply_to: request.body.id,
+                payload,
+            },
+        })
+    }
+
+    /// Asks for `event` to be injected back into the node after `after`.
+    pub fn schedule(&mut self, after: Duration, event: InjectedPayload) -> TimerId {
+        let id = self.timer();
+        self.effects.push(Effect::Timer {
+            id,
+            after,
+            every: None,
+            jitter: Duration::ZERO,
+            event,
+        });
+        id
+    }
+
+    /// Asks for `event` to be injected every `period`, each time up to
+    /// `jitter` late, until the timer is cancelled. Ticks missed while the
+    /// node was busy are coalesced into one. A `period` below
+    /// [`MIN_PERIOD`](crate::MIN_PERIOD) is raised to it.
+    pub fn every(&mut self, period: Duration, jitter: Duration, event: InjectedPayload) -> TimerId {
+        let id = self.timer();
+        self.effects.push(Effect::Timer {
+            id,
+            after: period,
+            every: Some(period),
+            jitter,
+            event,
+        });
+        id
+    }
+
+    /// Cancels a timer. Its event may still be injected if it was due before
+    /// this step.
+    pub fn cancel(&mut self, timer: TimerId) {
+        self.effects.push(Effect::Cancel(timer));
+    }
+
+    pub(crate) fn rpc_timeout(&mut self, id: usize, after: Duration) {
+        let timer = self.timer();
+        self.effects.push(Effect::RpcTimeout { timer, id, after });
+    }
+
+    fn timer(&mut self) -> TimerId {
+        self.next_timer += 1;
+        TimerId(self.next_timer)
+    }
+
+    pub fn log(&mut self, line: impl Into<String>) {
+        self.effects.push(Effect::Log(line.into()));
+    }
+
+    pub fn effects(&self) -> &[Effect<InjectedPayload>] {
+        &self.effects
+    }
+
+    pub fn take_effects(&mut self) -> Vec<Effect<InjectedPayload>> {
+        std::mem::take(&mut self.effects)
+    }
+}
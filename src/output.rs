@@ -0,0 +1,95 @@
+use std::fmt;
+use std::io::{self, Write};
+
+/// How much output [`BatchWriter`] holds back before writing it out anyway.
+pub const DEFAULT_BATCH_LIMIT: usize = 64 * 1024;
+
+/// Collects outgoing lines and hands them to the underlying writer in one
+/// write per batch, rather than one or more per message.
+///
+/// A batch goes out on [`flush`](Write::flush), which the main loop calls
+/// once it is done with an input, or once `limit` bytes have piled up.
+/// Batches always end on a line boundary.
+pub struct BatchWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+    limit: usize,
+    stats: OutputStats,
+}
+
+/// What a [`BatchWriter`] has written so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OutputStats {
+    pub lines: u64,
+    pub bytes: u64,
+    /// Writes to the underlying writer. Each line was one of these before
+    /// output was batched.
+    pub writes: u64,
+}
+
+impl fmt::Display for OutputStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} lines, {} bytes in {} writes",
+            self.lines, self.bytes, self.writes
+        )
+    }
+}
+
+impl<W: Write> BatchWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_limit(inner, DEFAULT_BATCH_LIMIT)
+    }
+
+    pub fn with_limit(inner: W, limit: usize) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(limit.min(DEFAULT_BATCH_LIMIT)),
+            limit,
+            stats: OutputStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> OutputStats {
+        self.stats
+    }
+
+    /// Writes out `buf` up to `end`.
+    fn write_out(&mut self, end: usize) -> io::Result<()> {
+        if end == 0 {
+            return Ok(());
+        }
+        self.inner.write_all(&self.buf[..end])?;
+        self.buf.drain(..end);
+        self.stats.writes += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BatchWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        self.stats.bytes += data.len() as u64;
+        self.stats.lines += data.iter().filter(|&&b| b == b'\n').count() as u64;
+        if self.buf.len() >= self.limit {
+            // Whatever follows the last newline is the start of a line still
+            // being written.
+            if let Some(end) = self.buf.iter().rposition(|&b| b == b'\n') {
+                self.write_out(end + 1)?;
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_out(self.buf.len())?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for BatchWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
@@ -0,0 +1,232 @@
+//! Nodes that handle each message `type` in a function of its own, rather
+//! than matching on one `Payload` enum.
+
+use crate::{Body, ErrorBody, ErrorCode, Event, Init, Message, Node, Outbox, RawPayload, Rpc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+type Handler<N, IP> =
+    Box<dyn Fn(&mut N, Message<RawPayload>, &mut Outbox<IP>) -> anyhow::Result<()>>;
+
+/// Dispatches messages to handlers registered per `type`, for nodes whose
+/// `Payload` is a [`RawPayload`]. Bodies are only parsed by the handler they
+/// are dispatched to.
+///
+/// Nodes usually implement [`Routes`] and run as a [`Routed`] node, which
+/// keeps the router next to them and hands it every message. Otherwise, a
+/// node can keep the router itself and call [`Router::dispatch`].
+pub struct Router<N, InjectedPayload = ()> {
+    handlers: HashMap<String, Handler<N, InjectedPayload>>,
+    fallback: Handler<N, InjectedPayload>,
+}
+
+impl<N: 'static, IP: 'static> Default for Router<N, IP> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N: 'static, IP: 'static> Router<N, IP> {
+    /// A router without handlers. Requests of types nothing is registered
+    /// for are answered with `not-supported`, stray replies are dropped.
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            fallback: Box::new(|_, msg, outbox| {
+                let kind = msg.body.payload.kind();
+                if msg.body.in_reply_to.is_some() {
+                    outbox.log(format!("ignoring {} from {}", kind, msg.src));
+                    return Ok(());
+                }
+                Err(ErrorBody::new(
+                    ErrorCode::NotSupported,
+                    format!("{} is not supported", kind),
+                )
+                .into())
+            }),
+        }
+    }
+
+    /// Answers `kind` requests with what `handler` returns for their body,
+    /// as a `{kind}_ok` reply. A response of `()` makes for an empty reply.
+    pub fn handle<Req, Resp, F>(self, kind: &str, handler: F) -> Self
+    where
+        Req: DeserializeOwned,
+        Resp: Serialize,
+        F: Fn(&mut N, Req) -> anyhow::Result<Resp> + 'static,
+    {
+        let reply_kind = format!("{}_ok", kind);
+        self.on(kind, move |node, request: Message<Req>, outbox| {
+            let response = handler(node, request.body.payload)?;
+            outbox.send(&Message {
+                src: request.dst,
+                dst: request.src,
+                body: Body {
+                    id: None,
+                    in_reply_to: request.body.id,
+                    payload: Tagged {
+                        kind: &reply_kind,
+                        payload: response,
+                    },
+                },
+            })?;
+            Ok(())
+        })
+    }
+
+    /// Hands `kind` messages to `handler` whole, for handlers that reply
+    /// later, more than once or not at all. A body that does not parse as
+    /// `Req` is answered with `malformed-request`.
+    pub fn on<Req, F>(mut self, kind: &str, handler: F) -> Self
+    where
+        Req: DeserializeOwned,
+        F: Fn(&mut N, Message<Req>, &mut Outbox<IP>) -> anyhow::Result<()> + 'static,
+    {
+        let name = kind.to_string();
+        let handler: Handler<N, IP> = Box::new(move |node, msg, outbox| {
+            let payload = match msg.body.payload.parse() {
+                Ok(payload) => payload,
+                Err(e) if msg.body.in_reply_to.is_some() => {
+                    outbox.log(format!("dropping {} from {}: {}", name, msg.src, e));
+                    return Ok(());
+                }
+                Err(e) => {
+                    return Err(ErrorBody::new(
+                        ErrorCode::MalformedRequest,
+                        format!("bad {}: {}", name, e),
+                    )
+                    .into())
+                }
+            };
+            handler(
+                node,
+                Message {
+                    src: msg.src,
+                    dst: msg.dst,
+                    body: Body {
+                        id: msg.body.id,
+                        in_reply_to: msg.body.in_reply_to,
+                        payload,
+                    },
+                },
+                outbox,
+            )
+        });
+        self.handlers.insert(kind.to_string(), handler);
+        self
+    }
+
+    /// Replaces the default handling of types nothing is registered for.
+    pub fn fallback<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&mut N, Message<RawPayload>, &mut Outbox<IP>) -> anyhow::Result<()> + 'static,
+    {
+        self.fallback = Box::new(handler);
+        self
+    }
+
+    pub fn dispatch(
+        &self,
+        node: &mut N,
+        msg: Message<RawPayload>,
+        outbox: &mut Outbox<IP>,
+    ) -> anyhow::Result<()> {
+        let handler = self
+            .handlers
+            .get(msg.body.payload.kind())
+            .unwrap_or(&self.fallback);
+        handler(node, msg, outbox)
+    }
+}
+
+/// A payload with its `type` put back in.
+#[derive(Serialize)]
+struct Tagged<'a, P> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    #[serde(flatten)]
+    payload: P,
+}
+
+/// A node whose messages all go through the [`Router`] it sets up. Run it
+/// as a [`Routed`] node.
+pub trait Routes<S, InjectedPayload = ()>: Sized + 'static {
+    fn from_init(
+        state: S,
+        init: Init,
+        inject: Sender<Event<RawPayload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>;
+
+    /// The handlers for the node's messages, set up once after `init`.
+    fn router() -> Router<Self, InjectedPayload>;
+
+    /// Handles what is not a message: injected events and EOF.
+    fn step(
+        &mut self,
+        _input: Event<RawPayload, InjectedPayload>,
+        _outbox: &mut Outbox<InjectedPayload>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// See [`Node::periodic`].
+    fn periodic(&self) -> Vec<(Duration, InjectedPayload)> {
+        Vec::new()
+    }
+
+    /// See [`Node::rpc`].
+    fn rpc(&self) -> Option<Rpc<RawPayload, InjectedPayload>> {
+        None
+    }
+}
+
+/// Runs a [`Routes`] node, dispatching its messages to its router.
+pub struct Routed<N, InjectedPayload = ()> {
+    node: N,
+    router: Router<N, InjectedPayload>,
+}
+
+impl<N, IP> Routed<N, IP> {
+    pub fn node(&self) -> &N {
+        &self.node
+    }
+}
+
+impl<S, N, IP> Node<S, RawPayload, IP> for Routed<N, IP>
+where
+    N: Routes<S, IP>,
+    IP: 'static,
+{
+    fn from_init(
+        state: S,
+        init: Init,
+        inject: Sender<Event<RawPayload, IP>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            node: N::from_init(state, init, inject)?,
+            router: N::router(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<RawPayload, IP>,
+        outbox: &mut Outbox<IP>,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::Message(msg) => self.router.dispatch(&mut self.node, msg, outbox),
+            input => self.node.step(input, outbox),
+        }
+    }
+
+    fn periodic(&self) -> Vec<(Duration, IP)> {
+        self.node.periodic()
+    }
+
+    fn rpc(&self) -> Option<Rpc<RawPayload, IP>> {
+        self.node.rpc()
+    }
+}
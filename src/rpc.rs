@@ -0,0 +1,136 @@
+use crate::{Body, ErrorBody, Event, Message, Outbox};
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum RpcError {
+    Timeout,
+    Remote(ErrorBody),
+    Malformed(serde_json::Error),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Timeout => write!(f, "rpc timed out"),
+            RpcError::Remote(e) => write!(f, "rpc failed remotely: {}", e),
+            RpcError::Malformed(e) => write!(f, "rpc reply could not be deserialized: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+type Continuation<IP> = Box<dyn FnOnce(Result<Message<Value>, RpcError>) -> IP + Send>;
+
+/// Tracks outstanding requests by `msg_id` and turns their replies (or
+/// timeouts) into injected events for the node that issued them.
+pub struct Rpc<Payload, InjectedPayload> {
+    pending: Arc<Mutex<HashMap<usize, Continuation<InjectedPayload>>>>,
+    inject: Sender<Event<Payload, InjectedPayload>>,
+}
+
+impl<Payload, InjectedPayload> Clone for Rpc<Payload, InjectedPayload> {
+    fn clone(&self) -> Self {
+        Self {
+            pending: self.pending.clone(),
+            inject: self.inject.clone(),
+        }
+    }
+}
+
+impl<Payload, InjectedPayload> Rpc<Payload, InjectedPayload>
+where
+    Payload: Send + 'static,
+    InjectedPayload: Send + 'static,
+{
+    pub fn new(inject: Sender<Event<Payload, InjectedPayload>>) -> Self {
+        Self {
+            pending: Default::default(),
+            inject,
+        }
+    }
+
+    /// Sends `request` and registers `k` to be run with its reply. Whatever `k`
+    /// returns is injected back into the node as `Event::Injected`.
+    pub fn call<Req, Resp, F>(
+        &self,
+        request: &Message<Req>,
+        timeout: Duration,
+        outbox: &mut Outbox<InjectedPayload>,
+        k: F,
+    ) -> anyhow::Result<()>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+        F: FnOnce(Result<Message<Resp>, RpcError>) -> InjectedPayload + Send + 'static,
+    {
+        let k: Continuation<InjectedPayload> =
+            Box::new(move |reply| k(reply.and_then(parse_reply)));
+        // The request only leaves the node once the step returns, so there
+        // is no race with its reply between sending and registering `k`.
+        let id = outbox
+            .send(request)
+            .with_context(|| format!("send rpc request to {}", request.dst))?;
+        self.pending.lock().unwrap().insert(id, k);
+        outbox.rpc_timeout(id, timeout);
+        Ok(())
+    }
+
+    /// Fails the call with `msg_id` `id` with [`RpcError::Timeout`], unless
+    /// its reply came in already. The runtime calls this when the timeout
+    /// that `call` scheduled is due.
+    pub(crate) fn expire(&self, id: usize) {
+        let Some(k) = self.pending.lock().unwrap().remove(&id) else {
+            return;
+        };
+        let _ = self.inject.send(Event::Injected(k(Err(RpcError::Timeout))));
+    }
+
+    /// Hands `reply` to the continuation waiting on it. Messages that are not
+    /// a reply to an outstanding call are given back.
+    pub fn resolve(&self, reply: Message<Value>) -> Result<(), Message<Value>> {
+        let Some(id) = reply.body.in_reply_to else {
+            return Err(reply);
+        };
+        let Some(k) = self.pending.lock().unwrap().remove(&id) else {
+            return Err(reply);
+        };
+        let _ = self.inject.send(Event::Injected(k(Ok(reply))));
+        Ok(())
+    }
+
+    /// Whether a call is waiting for the reply to `msg_id` `id`.
+    pub fn awaits(&self, id: usize) -> bool {
+        self.pending.lock().unwrap().contains_key(&id)
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+}
+
+/// Reads `reply` as a `Resp`, or as the error it carries instead.
+pub(crate) fn parse_reply<Resp: DeserializeOwned>(
+    reply: Message<Value>,
+) -> Result<Message<Resp>, RpcError> {
+    if reply.body.payload.get("type").and_then(Value::as_str) == Some("error") {
+        let error = serde_json::from_value(reply.body.payload).map_err(RpcError::Malformed)?;
+        return Err(RpcError::Remote(error));
+    }
+    Ok(Message {
+        src: reply.src,
+        dst: reply.dst,
+        body: Body {
+            id: reply.body.id,
+            in_reply_to: reply.body.in_reply_to,
+            payload: serde_json::from_value(reply.body.payload).map_err(RpcError::Malformed)?,
+        },
+    })
+}
@@ -0,0 +1,247 @@
+use crate::{Effect, Rpc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Where a [`Scheduler`] gets the time from. Only differences between two
+/// readings matter.
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+/// Real time, counted from when the clock was created.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Time that only moves when told to, as in the simulator. Clones share the
+/// same time.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Rc<Cell<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+/// The shortest period a periodic timer runs at. Shorter ones, zero in
+/// particular, are stretched to this, or they would be due again as soon as
+/// they fired.
+pub const MIN_PERIOD: Duration = Duration::from_millis(1);
+
+/// Handle to a timer, to cancel it with. Handed out by [`Outbox`](crate::Outbox).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(pub u64);
+
+#[derive(Debug)]
+struct Timer<T> {
+    deadline: Duration,
+    every: Option<Duration>,
+    jitter: Duration,
+    event: T,
+}
+
+/// Keeps timers and says which are due by its clock.
+///
+/// A periodic timer that falls behind, because nobody asked for expired
+/// timers for a while, fires once for all the ticks it missed and then
+/// carries on a full period from now.
+pub struct Scheduler<T, C = SystemClock> {
+    clock: C,
+    rng: StdRng,
+    timers: HashMap<TimerId, Timer<T>>,
+    /// Deadlines of cancelled or rearmed timers linger here until popped.
+    deadlines: BinaryHeap<Reverse<(Duration, TimerId)>>,
+}
+
+impl<T: Clone, C: Clock> Scheduler<T, C> {
+    /// `seed` drives the jitter.
+    pub fn new(clock: C, seed: u64) -> Self {
+        Self {
+            clock,
+            rng: StdRng::seed_from_u64(seed),
+            timers: HashMap::new(),
+            deadlines: BinaryHeap::new(),
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    /// Arms timer `id` to deliver `event` after `after` plus up to `jitter`,
+    /// and then every `every` (plus jitter) if given, but no more often than
+    /// every [`MIN_PERIOD`]. Rearms `id` if it is already armed.
+    pub fn insert(
+        &mut self,
+        id: TimerId,
+        after: Duration,
+        every: Option<Duration>,
+        jitter: Duration,
+        event: T,
+    ) {
+        let every = every.map(|every| every.max(MIN_PERIOD));
+        let deadline = self.clock.now() + after + self.jitter(jitter);
+        self.deadlines.push(Reverse((deadline, id)));
+        self.timers.insert(
+            id,
+            Timer {
+                deadline,
+                every,
+                jitter,
+                event,
+            },
+        );
+    }
+
+    /// Returns whether `id` was still armed.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        self.timers.remove(&id).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// When the next timer is due, by the clock.
+    pub fn next_deadline(&mut self) -> Option<Duration> {
+        while let Some(&Reverse((deadline, id))) = self.deadlines.peek() {
+            if self.timers.get(&id).is_some_and(|t| t.deadline == deadline) {
+                return Some(deadline);
+            }
+            self.deadlines.pop();
+        }
+        None
+    }
+
+    /// How long until the next timer is due.
+    pub fn until_next(&mut self) -> Option<Duration> {
+        let now = self.clock.now();
+        self.next_deadline().map(|d| d.saturating_sub(now))
+    }
+
+    /// The events of all timers that are due, in deadline order.
+    pub fn expired(&mut self) -> Vec<T> {
+        let now = self.clock.now();
+        let mut events = Vec::new();
+        while let Some(deadline) = self.next_deadline().filter(|d| *d <= now) {
+            let Reverse((_, id)) = self.deadlines.pop().expect("peeked");
+            let timer = self.timers.get_mut(&id).expect("deadline is live");
+            events.push(timer.event.clone());
+            let Some(every) = timer.every else {
+                self.timers.remove(&id);
+                continue;
+            };
+            let jitter = timer.jitter;
+            let mut next = deadline + every;
+            if next <= now {
+                next = now + every;
+            }
+            next += self.jitter(jitter);
+            let timer = self.timers.get_mut(&id).expect("deadline is live");
+            timer.deadline = next;
+            self.deadlines.push(Reverse((next, id)));
+        }
+        events
+    }
+
+    fn jitter(&mut self, max: Duration) -> Duration {
+        if max.is_zero() {
+            Duration::ZERO
+        } else {
+            self.rng.gen_range(Duration::ZERO..=max)
+        }
+    }
+}
+
+/// What a runtime's scheduler holds: events for the node, and deadlines of
+/// its [`Rpc`] calls.
+#[derive(Debug, Clone)]
+pub(crate) enum Wake<IP> {
+    Inject(IP),
+    Expire(usize),
+}
+
+impl<IP: Clone, C: Clock> Scheduler<Wake<IP>, C> {
+    /// Takes care of the timer effects and gives back the rest.
+    pub(crate) fn apply(&mut self, effect: Effect<IP>) -> Option<Effect<IP>> {
+        match effect {
+            Effect::Timer {
+                id,
+                after,
+                every,
+                jitter,
+                event,
+            } => self.insert(id, after, every, jitter, Wake::Inject(event)),
+            Effect::Cancel(id) => {
+                self.cancel(id);
+            }
+            Effect::RpcTimeout { timer, id, after } => {
+                self.insert(timer, after, None, Duration::ZERO, Wake::Expire(id))
+            }
+            effect => return Some(effect),
+        }
+        None
+    }
+}
+
+impl<IP> Wake<IP> {
+    /// Turns a due timer into the event to step the node with, if any.
+    pub(crate) fn fire<P>(self, rpc: Option<&Rpc<P, IP>>) -> Option<IP>
+    where
+        P: Send + 'static,
+        IP: Send + 'static,
+    {
+        match self {
+            Wake::Inject(event) => Some(event),
+            Wake::Expire(id) => {
+                if let Some(rpc) = rpc {
+                    rpc.expire(id);
+                }
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use crate::kv::KvPayload;
+use crate::{Body, ErrorBody, ErrorCode, ErrorPayload, Message, NodeId};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum TsoPayload {
+    Ts,
+    TsOk { ts: u64 },
+}
+
+/// In-process stand-in for one of Maelstrom's built-in services. Requests and
+/// replies are raw JSON messages, exactly as they would appear on the wire.
+pub trait Service: Send {
+    fn handle(&mut self, request: Message<Value>) -> Message<Value>;
+}
+
+/// Routes messages addressed to `seq-kv`, `lin-kv`, `lww-kv` and `lin-tso`.
+pub struct Services {
+    services: HashMap<NodeId, Box<dyn Service>>,
+}
+
+impl Services {
+    pub fn new(seed: u64) -> Self {
+        let mut services: HashMap<NodeId, Box<dyn Service>> = HashMap::new();
+        services.insert("seq-kv".into(), Box::new(SeqKv::new(seed)));
+        services.insert("lin-kv".into(), Box::new(LinKv::default()));
+        services.insert("lww-kv".into(), Box::new(LwwKv::new(seed, 3)));
+        services.insert("lin-tso".into(), Box::new(LinTso::default()));
+        Self { services }
+    }
+
+    pub fn contains(&self, name: NodeId) -> bool {
+        self.services.contains_key(&name)
+    }
+
+    /// Returns `None` if `request` is not addressed to one of the services.
+    pub fn handle(&mut self, request: Message<Value>) -> Option<Message<Value>> {
+        let service = self.services.get_mut(&request.dst)?;
+        Some(service.handle(request))
+    }
+}
+
+fn reply<P: Serialize>(request: &Message<Value>, id: &mut usize, payload: P) -> Message<Value> {
+    let mid = *id;
+    *id += 1;
+    Message {
+        src: request.dst,
+        dst: request.src,
+        body: Body {
+            id: Some(mid),
+            in_reply_to: request.body.id,
+            payload: serde_json::to_value(payload).expect("service payloads serialize"),
+        },
+    }
+}
+
+fn error_reply(request: &Message<Value>, id: &mut usize, error: ErrorBody) -> Message<Value> {
+    reply(request, id, ErrorPayload::Error(error))
+}
+
+fn parse<P: for<'de> Deserialize<'de>>(
+    request: &Message<Value>,
+    known: &[&str],
+) -> Result<P, ErrorBody> {
+    let kind = request.body.payload.get("type").and_then(Value::as_str);
+    match kind {
+        Some(kind) if known.contains(&kind) => serde_json::from_value(request.body.payload.clone())
+            .map_err(|e| ErrorBody::new(ErrorCode::MalformedRequest, e.to_string())),
+        Some(kind) => Err(ErrorBody::new(
+            ErrorCode::NotSupported,
+            format!("{} is not supported", kind),
+        )),
+        None => Err(ErrorBody::new(
+            ErrorCode::MalformedRequest,
+            "message has no type",
+        )),
+    }
+}
+
+const KV_REQUESTS: &[&str] = &["read", "write", "cas"];
+
+fn store_key(key: &Value) -> String {
+    key.to_string()
+}
+
+#[derive(Debug, Clone, Default)]
+struct Store {
+    values: BTreeMap<String, Value>,
+}
+
+impl Store {
+    fn read(&self, key: &Value) -> Result<KvPayload, ErrorBody> {
+        match self.values.get(&store_key(key)) {
+            Some(value) => Ok(KvPayload::ReadOk {
+                value: value.clone(),
+            }),
+            None => Err(ErrorBody::new(
+                ErrorCode::KeyDoesNotExist,
+                "key does not exist",
+            )),
+        }
+    }
+
+    fn apply(&mut self, op: KvPayload) -> Result<KvPayload, ErrorBody> {
+        match op {
+            KvPayload::Read { key } => self.read(&key),
+            KvPayload::Write { key, value } => {
+                self.values.insert(store_key(&key), value);
+                Ok(KvPayload::WriteOk)
+            }
+            KvPayload::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.values.get_mut(&store_key(&key)) {
+                Some(current) if *current == from => {
+                    *current = to;
+                    Ok(KvPayload::CasOk)
+                }
+                Some(current) => Err(ErrorBody::new(
+                    ErrorCode::PreconditionFailed,
+                    format!("expected {}, but had {}", from, current),
+                )),
+                None if create_if_not_exists => {
+                    self.values.insert(store_key(&key), to);
+                    Ok(KvPayload::CasOk)
+                }
+                None => Err(ErrorBody::new(
+                    ErrorCode::KeyDoesNotExist,
+                    "key does not exist",
+                )),
+            },
+            KvPayload::ReadOk { .. } | KvPayload::WriteOk | KvPayload::CasOk => Err(
+                ErrorBody::new(ErrorCode::NotSupported, "replies are not requests"),
+            ),
+        }
+    }
+}
+
+/// Linearizable kv: one copy of the data, every operation applied in arrival
+/// order.
+#[derive(Debug, Default)]
+pub struct LinKv {
+    id: usize,
+    store: Store,
+}
+
+impl Service for LinKv {
+    fn handle(&mut self, request: Message<Value>) -> Message<Value> {
+        match parse(&request, KV_REQUESTS).and_then(|op| self.store.apply(op)) {
+            Ok(payload) => reply(&request, &mut self.id, payload),
+            Err(e) => error_reply(&request, &mut self.id, e),
+        }
+    }
+}
+
+/// Sequentially consistent kv: updates are totally ordered, but a read may
+/// observe any state at least as new as the last one its client observed.
+pub struct SeqKv {
+    id: usize,
+    rng: StdRng,
+    versions: VecDeque<Store>,
+    oldest: usize,
+    observed: HashMap<NodeId, usize>,
+}
+
+/// How many versions [`SeqKv`] keeps. Reads are served from these only, so
+/// a client that has fallen further behind jumps ahead to the oldest one.
+pub const SEQ_KV_HISTORY: usize = 16;
+
+impl SeqKv {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            id: 0,
+            rng: StdRng::seed_from_u64(seed),
+            versions: VecDeque::from([Store::default()]),
+            oldest: 0,
+            observed: HashMap::new(),
+        }
+    }
+
+    fn latest(&self) -> usize {
+        self.oldest + self.versions.len() - 1
+    }
+
+    fn apply(&mut self, client: NodeId, op: KvPayload) -> Result<KvPayload, ErrorBody> {
+        if let KvPayload::Read { key } = &op {
+            let floor = self
+                .observed
+                .get(&client)
+                .copied()
+                .unwrap_or(0)
+                .max(self.oldest);
+            let version = self.rng.gen_range(floor..=self.latest());
+            self.observed.insert(client, version);
+            return self.versions[version - self.oldest].read(key);
+        }
+
+        let mut next = self
+            .versions
+            .back()
+            .expect("history is never empty")
+            .clone();
+        let result = next.apply(op);
+        if result.is_err() {
+            self.observed.insert(client, self.latest());
+            return result;
+        }
+        self.versions.push_back(next);
+        if self.versions.len() > SEQ_KV_HISTORY {
+            self.versions.pop_front();
+            self.oldest += 1;
+        }
+        self.observed.insert(client, self.latest());
+        result
+    }
+}
+
+impl Service for SeqKv {
+    fn handle(&mut self, request: Message<Value>) -> Message<Value> {
+        match parse(&request, KV_REQUESTS).and_then(|op| self.apply(request.src, op)) {
+            Ok(payload) => reply(&request, &mut self.id, payload),
+            Err(e) => error_reply(&request, &mut self.id, e),
+        }
+    }
+}
+
+/// Last-write-wins kv: each operation hits one of several replicas, which
+/// converge by keeping the value with the highest timestamp. Reads can be
+/// stale and concurrent writes can be lost.
+pub struct LwwKv {
+    id: usize,
+    rng: StdRng,
+    clock: u64,
+    replicas: Vec<BTreeMap<String, (u64, Value)>>,
+}
+
+impl LwwKv {
+    pub fn new(seed: u64, replicas: usize) -> Self {
+        Self {
+            id: 0,
+            rng: StdRng::seed_from_u64(seed),
+            clock: 0,
+            replicas: vec![BTreeMap::new(); replicas.max(1)],
+        }
+    }
+
+    fn apply(&mut self, op: KvPayload) -> Result<KvPayload, ErrorBody> {
+        let r = self.rng.gen_range(0..self.replicas.len());
+        let mut store = Store {
+            values: self.replicas[r]
+                .iter()
+                .map(|(k, (_, v))| (k.clone(), v.clone()))
+                .collect(),
+        };
+        let written = match &op {
+            KvPayload::Write { key, .. } | KvPayload::Cas { key, .. } => Some(store_key(key)),
+            _ => None,
+        };
+        let result = store.apply(op)?;
+        if let Some(key) = written {
+            self.clock += 1;
+            let value = store.values.remove(&key).expect("written key is present");
+            self.replicas[r].insert(key, (self.clock, value));
+        }
+
+        let to = self.rng.gen_range(0..self.replicas.len());
+        let from = self.replicas[r].clone();
+        for (key, (ts, value)) in from {
+            let entry = self.replicas[to].entry(key).or_insert((0, Value::Null));
+            if entry.0 < ts {
+                *entry = (ts, value);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Service for LwwKv {
+    fn handle(&mut self, request: Message<Value>) -> Message<Value> {
+        match parse(&request, KV_REQUESTS).and_then(|op| self.apply(op)) {
+            Ok(payload) => reply(&request, &mut self.id, payload),
+            Err(e) => error_reply(&request, &mut self.id, e),
+        }
+    }
+}
+
+/// Linearizable timestamp oracle handing out strictly increasing `ts` values.
+#[derive(Debug, Default)]
+pub struct LinTso {
+    id: usize,
+    ts: u64,
+}
+
+impl Service for LinTso {
+    fn handle(&mut self, request: Message<Value>) -> Message<Value> {
+        match parse(&request, &["ts"]) {
+            Ok(TsoPayload::Ts) => {
+                let ts = self.ts;
+                self.ts += 1;
+                reply(&request, &mut self.id, TsoPayload::TsOk { ts })
+            }
+            Ok(TsoPayload::TsOk { .. }) => error_reply(
+                &request,
+                &mut self.id,
+                ErrorBody::new(ErrorCode::NotSupported, "replies are not requests"),
+            ),
+            Err(e) => error_reply(&request, &mut self.id, e),
+        }
+    }
+}
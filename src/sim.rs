@@ -0,0 +1,469 @@
+use crate::faults::{Faults, Network};
+use crate::scheduler::Wake;
+use crate::services::Services;
+use crate::workload::{Clients, Stats, Workload};
+use crate::{
+    arm_periodic, decode, Decoded, Effect, Event, Init, ManualClock, Message, Node, NodeId, Origin,
+    Outbox, Rpc, Scheduler,
+};
+use anyhow::Context;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap};
+use std::marker::PhantomData;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+enum Action {
+    Deliver {
+        dst: NodeId,
+        line: String,
+    },
+    /// Check the timers of `node`.
+    Wake {
+        node: NodeId,
+    },
+}
+
+struct Scheduled {
+    at: Duration,
+    seq: u64,
+    action: Action,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+struct SimNode<N, P, IP> {
+    node: N,
+    inject: Receiver<Event<P, IP>>,
+    rpc: Option<Rpc<P, IP>>,
+    /// Kept across steps so that `msg_id`s keep counting up.
+    outbox: Outbox<IP>,
+    timers: Scheduler<Wake<IP>, ManualClock>,
+    /// When the next `Action::Wake` for this node is queued.
+    wake: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RunOptions {
+    pub time_limit: Duration,
+    pub rate: f64,
+    pub concurrency: usize,
+    pub recovery: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            time_limit: Duration::from_secs(10),
+            rate: 50.0,
+            concurrency: 4,
+            recovery: Duration::from_secs(2),
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Runs a whole cluster of `N` in one thread against a virtual clock. Message
+/// latencies, timer phases and client operations are all drawn from one
+/// seeded RNG, so a run is reproduced exactly by reusing its seed.
+///
+/// Timers, including [`Rpc`] timeouts, run on the same virtual clock.
+pub struct Simulation<S, N, P, IP> {
+    rng: StdRng,
+    now: Duration,
+    clock: ManualClock,
+    seq: u64,
+    latency: (Duration, Duration),
+    queue: BinaryHeap<Reverse<Scheduled>>,
+    nodes: BTreeMap<NodeId, SimNode<N, P, IP>>,
+    node_ids: Vec<NodeId>,
+    services: Services,
+    network: Network,
+    network_changes: Vec<(Duration, String)>,
+    inbox: Vec<Message<Value>>,
+    logs: Vec<(Duration, NodeId, String)>,
+    delivered: usize,
+    strict: bool,
+    state: PhantomData<S>,
+}
+
+impl<S, N, P, IP> Simulation<S, N, P, IP>
+where
+    S: Clone,
+    N: Node<S, P, IP>,
+    P: DeserializeOwned + Send + 'static,
+    IP: Clone + Send + 'static,
+{
+    pub fn new(seed: u64, node_count: usize, state: S) -> anyhow::Result<Self> {
+        let node_ids: Vec<NodeId> = (0..node_count as u32).map(NodeId::server).collect();
+        let mut sim = Self {
+            rng: StdRng::seed_from_u64(seed),
+            now: Duration::ZERO,
+            clock: ManualClock::new(),
+            seq: 0,
+            latency: (Duration::from_millis(1), Duration::from_millis(5)),
+            queue: BinaryHeap::new(),
+            nodes: BTreeMap::new(),
+            node_ids: node_ids.clone(),
+            services: Services::new(seed),
+            network: Network::new(Faults::default(), seed),
+            network_changes: Vec::new(),
+            inbox: Vec::new(),
+            logs: Vec::new(),
+            delivered: 0,
+            strict: false,
+            state: PhantomData,
+        };
+        for &id in &node_ids {
+            let (tx, rx) = channel();
+            let init = Init {
+                node_id: id,
+                node_ids: node_ids.clone(),
+            };
+            let node = N::from_init(state.clone(), init, tx)
+                .with_context(|| format!("initialize {}", id))?;
+            let mut outbox = Outbox::new();
+            arm_periodic(&node, &mut outbox);
+            let timers = Scheduler::new(sim.clock.clone(), sim.rng.gen());
+            sim.nodes.insert(
+                id,
+                SimNode {
+                    rpc: node.rpc(),
+                    node,
+                    inject: rx,
+                    outbox,
+                    timers,
+                    wake: None,
+                },
+            );
+            sim.flush(id)?;
+        }
+        Ok(sim)
+    }
+
+    /// Makes input a node cannot decode fail the run instead of being
+    /// logged and skipped (or answered with an error).
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_latency(mut self, min: Duration, max: Duration) -> Self {
+        self.latency = (min, max.max(min));
+        self
+    }
+
+    pub fn with_faults(mut self, faults: Faults) -> Self {
+        self.network = Network::new(faults, self.rng.gen());
+        self
+    }
+
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn node_ids(&self) -> &[NodeId] {
+        &self.node_ids
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&N> {
+        self.nodes.get(&id).map(|n| &n.node)
+    }
+
+    /// Every partition and heal so far, with the time it happened.
+    pub fn network_changes(&self) -> &[(Duration, String)] {
+        &self.network_changes
+    }
+
+    /// Everything the nodes logged, as (time, node, line).
+    pub fn logs(&self) -> &[(Duration, NodeId, String)] {
+        &self.logs
+    }
+
+    /// Number of messages handed to a node or service so far.
+    pub fn delivered(&self) -> usize {
+        self.delivered
+    }
+
+    /// Puts `msg` on the network as if a client had sent it just now.
+    pub fn send<T: Serialize>(&mut self, msg: &Message<T>) -> anyhow::Result<()> {
+        let line = serde_json::to_string(msg).context("serialize client message")?;
+        self.transmit(msg.src, msg.dst, line);
+        Ok(())
+    }
+
+    /// Puts `line` on the network as is, as if `src` had sent it to `dst`
+    /// just now, for input that is not a well-formed message.
+    pub fn send_line(&mut self, src: NodeId, dst: NodeId, line: impl Into<String>) {
+        self.transmit(src, dst, line.into());
+    }
+
+    /// Messages the nodes have addressed to clients since the last call.
+    pub fn take_client_messages(&mut self) -> Vec<Message<Value>> {
+        std::mem::take(&mut self.inbox)
+    }
+
+    pub fn run_for(&mut self, duration: Duration) -> anyhow::Result<()> {
+        self.run_until(self.now + duration)
+    }
+
+    pub fn run_until(&mut self, until: Duration) -> anyhow::Result<()> {
+        while let Some(Reverse(next)) = self.queue.peek() {
+            if next.at > until {
+                break;
+            }
+            let Reverse(next) = self.queue.pop().expect("just peeked");
+            self.now = next.at;
+            self.clock.set(self.now);
+            match next.action {
+                Action::Deliver { dst, line } => self.deliver(dst, line)?,
+                Action::Wake { node } => self.wake(node)?,
+            }
+        }
+        self.now = self.now.max(until);
+        self.clock.set(self.now);
+        Ok(())
+    }
+
+    /// Drives `workload` against the cluster the way `rustengan-run` does
+    /// against real processes.
+    pub fn run_workload(
+        &mut self,
+        workload: &mut dyn Workload,
+        options: RunOptions,
+    ) -> anyhow::Result<Stats> {
+        let mut clients = Clients::new(options.concurrency, options.timeout);
+
+        let setup = workload.setup(&self.node_ids);
+        self.request_all(&mut clients, setup)?;
+        self.settle(&mut clients, workload, options.timeout)?;
+
+        let interval = Duration::from_secs_f64(1.0 / options.rate.max(0.001));
+        let stop = self.now + options.time_limit;
+        let mut next_op = self.now;
+        while next_op < stop {
+            self.run_until(next_op)?;
+            self.hand_to_clients(&mut clients, workload);
+            if clients.outstanding() < options.concurrency {
+                let node = self.node_ids[self.rng.gen_range(0..self.node_ids.len())];
+                let payload = workload.generate(&mut self.rng);
+                let request = clients.request(node, payload, self.now);
+                self.send(&request)?;
+            }
+            next_op += interval;
+        }
+
+        self.run_until(stop)?;
+        if self.network.heal() {
+            self.network_changes
+                .push((self.now, "healed network".to_string()));
+        }
+        self.run_until(stop + options.recovery)?;
+        self.hand_to_clients(&mut clients, workload);
+        let finish = workload.finish(&self.node_ids);
+        self.request_all(&mut clients, finish)?;
+        self.settle(&mut clients, workload, options.timeout)?;
+        clients.expire(self.now + options.timeout, workload);
+        Ok(clients.stats())
+    }
+
+    fn request_all(
+        &mut self,
+        clients: &mut Clients,
+        requests: Vec<(NodeId, Value)>,
+    ) -> anyhow::Result<()> {
+        for (node, payload) in requests {
+            let request = clients.request(node, payload, self.now);
+            self.send(&request)?;
+        }
+        Ok(())
+    }
+
+    fn settle(
+        &mut self,
+        clients: &mut Clients,
+        workload: &mut dyn Workload,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let deadline = self.now + timeout;
+        while clients.outstanding() > 0 && self.now < deadline {
+            self.run_for(Duration::from_millis(10))?;
+            self.hand_to_clients(clients, workload);
+        }
+        Ok(())
+    }
+
+    fn hand_to_clients(&mut self, clients: &mut Clients, workload: &mut dyn Workload) {
+        for reply in self.take_client_messages() {
+            clients.receive(reply, workload);
+        }
+        clients.expire(self.now, workload);
+    }
+
+    fn schedule(&mut self, at: Duration, action: Action) {
+        self.seq += 1;
+        self.queue.push(Reverse(Scheduled {
+            at,
+            seq: self.seq,
+            action,
+        }));
+    }
+
+    fn transmit(&mut self, src: NodeId, dst: NodeId, line: String) {
+        if let Some(change) = self.network.advance(self.now, &self.node_ids) {
+            self.network_changes.push((self.now, change));
+        }
+        for extra in self.network.deliveries(src, dst) {
+            let (min, max) = self.latency;
+            let latency = if max > min {
+                self.rng.gen_range(min..=max)
+            } else {
+                min
+            };
+            self.schedule(
+                self.now + latency + extra,
+                Action::Deliver {
+                    dst,
+                    line: line.clone(),
+                },
+            );
+        }
+    }
+
+    fn deliver(&mut self, dst: NodeId, line: String) -> anyhow::Result<()> {
+        if dst.is_client() {
+            let msg = serde_json::from_str(&line).context("message to client")?;
+            self.inbox.push(msg);
+            return Ok(());
+        }
+        self.delivered += 1;
+        if self.services.contains(dst) {
+            let request = serde_json::from_str(&line).context("message to service")?;
+            let reply = self.services.handle(request).expect("service exists");
+            let line = serde_json::to_string(&reply).context("serialize service reply")?;
+            self.transmit(reply.src, reply.dst, line);
+            return Ok(());
+        }
+        let Some(sim) = self.nodes.get(&dst) else {
+            return Ok(());
+        };
+        let decoded = decode(&line, sim.rpc.as_ref(), self.strict)
+            .with_context(|| format!("{} could not decode {}", dst, line))?;
+        match decoded {
+            Decoded::Message(msg) => self.step(dst, Event::Message(msg))?,
+            Decoded::Resolved => self.drain_injected(dst)?,
+            Decoded::Dropped(reason) => self.logs.push((self.now, dst, reason)),
+            Decoded::Rejected(reply, reason) => {
+                let sim = self.nodes.get_mut(&dst).expect("decoded for a known node");
+                sim.outbox.log(reason);
+                sim.outbox.send(&reply)?;
+                self.flush(dst)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, id: NodeId, event: Event<P, IP>) -> anyhow::Result<()> {
+        let sim = self.nodes.get_mut(&id).expect("stepping a known node");
+        let origin = Origin::of(&event);
+        let outbox = &mut sim.outbox;
+        if let Err(e) = sim.node.step(event, outbox) {
+            let Some(reply) = origin.error_reply(&e) else {
+                return Err(e).with_context(|| format!("{} failed at {:?}", id, self.now));
+            };
+            outbox.log(format!("replying with error to {}: {:#}", reply.dst, e));
+            outbox.send(&reply)?;
+        }
+        self.flush(id)
+    }
+
+    /// Carries out the effects `id` queued in its outbox, then lets it react
+    /// to whatever its rpc continuations injected.
+    fn flush(&mut self, id: NodeId) -> anyhow::Result<()> {
+        let sim = self.nodes.get_mut(&id).expect("flushing a known node");
+        let mut sent = Vec::new();
+        for effect in sim.outbox.take_effects() {
+            match sim.timers.apply(effect) {
+                Some(Effect::Send(msg)) => sent.push(msg),
+                Some(Effect::Log(line)) => self.logs.push((self.now, id, line)),
+                _ => {}
+            }
+        }
+        for msg in sent {
+            let line =
+                serde_json::to_string(&msg).with_context(|| format!("{} emitted {:?}", id, msg))?;
+            self.transmit(id, msg.dst, line);
+        }
+        self.rearm(id);
+        self.drain_injected(id)
+    }
+
+    /// Fires the timers of `id` that are due.
+    fn wake(&mut self, id: NodeId) -> anyhow::Result<()> {
+        let Some(sim) = self.nodes.get_mut(&id) else {
+            return Ok(());
+        };
+        if sim.wake == Some(self.now) {
+            sim.wake = None;
+        }
+        let due = sim.timers.expired();
+        let rpc = sim.rpc.clone();
+        for wake in due {
+            match wake.fire(rpc.as_ref()) {
+                Some(event) => self.step(id, Event::Injected(event))?,
+                None => self.drain_injected(id)?,
+            }
+        }
+        self.rearm(id);
+        Ok(())
+    }
+
+    /// Makes sure an `Action::Wake` is queued for the next timer of `id`.
+    fn rearm(&mut self, id: NodeId) {
+        let sim = self.nodes.get_mut(&id).expect("rearming a known node");
+        let Some(deadline) = sim.timers.next_deadline() else {
+            return;
+        };
+        if sim.wake.is_some_and(|wake| wake <= deadline) {
+            return;
+        }
+        sim.wake = Some(deadline);
+        self.schedule(deadline, Action::Wake { node: id });
+    }
+
+    fn drain_injected(&mut self, id: NodeId) -> anyhow::Result<()> {
+        let sim = self.nodes.get_mut(&id).expect("draining a known node");
+        let injected: Vec<_> = sim.inject.try_iter().collect();
+        for event in injected {
+            if let Event::EOF = event {
+                continue;
+            }
+            self.step(id, event)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+//! Where a node's input comes from and its output goes to. Maelstrom talks
+//! to nodes over stdin and stdout, but the same nodes can just as well run
+//! over sockets or, in tests, over in-memory pipes.
+
+use std::io::{self, BufRead, BufReader, LineWriter, Read, Stdin, StdoutLock, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// A duplex stream of newline-delimited messages.
+pub trait Transport {
+    /// Read on a thread of its own, hence `Send`.
+    type Reader: BufRead + Send + 'static;
+    type Writer: Write;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)>;
+}
+
+/// stdin and stdout, as Maelstrom runs nodes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stdio;
+
+impl Transport for Stdio {
+    type Reader = BufReader<Stdin>;
+    type Writer = StdoutLock<'static>;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        Ok((BufReader::new(io::stdin()), io::stdout().lock()))
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        TcpStream::connect(addr).map(Self::from)
+    }
+}
+
+impl From<TcpStream> for TcpTransport {
+    fn from(stream: TcpStream) -> Self {
+        Self { stream }
+    }
+}
+
+impl Transport for TcpTransport {
+    type Reader = BufReader<TcpStream>;
+    type Writer = LineWriter<TcpStream>;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        self.stream.set_nodelay(true)?;
+        let reader = BufReader::new(self.stream.try_clone()?);
+        Ok((reader, LineWriter::new(self.stream)))
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct UnixTransport {
+    stream: UnixStream,
+}
+
+#[cfg(unix)]
+impl UnixTransport {
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        UnixStream::connect(path).map(Self::from)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for UnixTransport {
+    fn from(stream: UnixStream) -> Self {
+        Self { stream }
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixTransport {
+    type Reader = BufReader<UnixStream>;
+    type Writer = LineWriter<UnixStream>;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        let reader = BufReader::new(self.stream.try_clone()?);
+        Ok((reader, LineWriter::new(self.stream)))
+    }
+}
+
+/// One end of an in-memory pipe, see [`MemoryTransport::pair`].
+#[derive(Debug)]
+pub struct MemoryTransport {
+    reader: MemoryReader,
+    writer: MemoryWriter,
+}
+
+impl MemoryTransport {
+    /// Two ends of a pipe: what one end writes, the other reads. Input on
+    /// one end ends once the other end's writer is dropped.
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, a_rx) = channel();
+        let (b_tx, b_rx) = channel();
+        let end = |tx, rx| MemoryTransport {
+            reader: MemoryReader {
+                rx,
+                buf: Vec::new(),
+                pos: 0,
+            },
+            writer: MemoryWriter {
+                tx,
+                buf: Vec::new(),
+            },
+        };
+        (end(a_tx, b_rx), end(b_tx, a_rx))
+    }
+}
+
+impl Transport for MemoryTransport {
+    type Reader = MemoryReader;
+    type Writer = MemoryWriter;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        Ok((self.reader, self.writer))
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryReader {
+    rx: Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for MemoryReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for MemoryReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            match self.rx.recv() {
+                Ok(chunk) => {
+                    self.buf = chunk;
+                    self.pos = 0;
+                }
+                // The writer is gone: end of input.
+                Err(_) => return Ok(&[]),
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.pos = (self.pos + n).min(self.buf.len());
+    }
+}
+
+/// Passes on whole lines; a partial line waits for its newline or a flush.
+#[derive(Debug)]
+pub struct MemoryWriter {
+    tx: Sender<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if let Some(end) = self.buf.iter().rposition(|&b| b == b'\n') {
+            let rest = self.buf.split_off(end + 1);
+            let lines = std::mem::replace(&mut self.buf, rest);
+            self.tx
+                .send(lines)
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.tx
+            .send(std::mem::take(&mut self.buf))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+impl Drop for MemoryWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// The transport `RUSTENGAN_TRANSPORT` asks for: `stdio` (the default),
+/// `tcp:HOST:PORT` or `unix:PATH`, the latter two connecting to whatever
+/// routes messages between the nodes.
+pub(crate) enum Endpoint {
+    Stdio,
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let Some(spec) = std::env::var_os("RUSTENGAN_TRANSPORT") else {
+            return Ok(Endpoint::Stdio);
+        };
+        let spec = spec
+            .into_string()
+            .map_err(|spec| anyhow::anyhow!("RUSTENGAN_TRANSPORT is not utf-8: {:?}", spec))?;
+        match spec.split_once(':') {
+            _ if spec.is_empty() || spec == "stdio" => Ok(Endpoint::Stdio),
+            Some(("tcp", addr)) => Ok(Endpoint::Tcp(addr.to_string())),
+            #[cfg(unix)]
+            Some(("unix", path)) => Ok(Endpoint::Unix(PathBuf::from(path))),
+            _ => anyhow::bail!("unknown RUSTENGAN_TRANSPORT {}", spec),
+        }
+    }
+}
@@ -0,0 +1,567 @@
+use crate::{Body, ErrorBody, Message, NodeId};
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+/// What a client learned from one of its requests.
+pub enum Outcome<'a> {
+    Ok(&'a Value),
+    /// The node replied with a definite error: the request had no effect.
+    Failed(ErrorBody),
+    /// Timed out or failed indefinitely: the request may or may not have
+    /// taken effect.
+    Indeterminate,
+}
+
+/// Generates client operations for one of the challenge workloads and checks
+/// the replies the nodes give to them.
+pub trait Workload: Send {
+    fn name(&self) -> &'static str;
+
+    /// Requests sent to the nodes once, after init and before any operation.
+    fn setup(&mut self, _nodes: &[NodeId]) -> Vec<(NodeId, Value)> {
+        Vec::new()
+    }
+
+    fn generate(&mut self, rng: &mut StdRng) -> Value;
+
+    fn complete(&mut self, node: NodeId, request: &Value, outcome: Outcome);
+
+    /// Requests sent once the cluster has been given time to converge, whose
+    /// replies are checked for the final state.
+    fn finish(&mut self, _nodes: &[NodeId]) -> Vec<(NodeId, Value)> {
+        Vec::new()
+    }
+
+    fn check(&self) -> anyhow::Result<()>;
+}
+
+pub fn by_name(name: &str) -> Option<Box<dyn Workload>> {
+    Some(match name {
+        "echo" => Box::new(Echo::default()),
+        "unique-ids" => Box::new(UniqueIds::default()),
+        "broadcast" => Box::new(Broadcast::default()),
+        "g-counter" => Box::new(GCounter::default()),
+        "pn-counter" => Box::new(PnCounter::default()),
+        "kafka" => Box::new(Kafka::default()),
+        _ => return None,
+    })
+}
+
+/// Name of the binary in this crate that implements `workload`.
+pub fn default_bin(workload: &str) -> Option<&'static str> {
+    Some(match workload {
+        "echo" => "echo",
+        "unique-ids" => "unique-ids",
+        "broadcast" => "broadcast",
+        "g-counter" => "counter",
+        "pn-counter" => "pn-counter",
+        "kafka" => "kafkalog",
+        _ => return None,
+    })
+}
+
+/// How many nodes the binary [`default_bin`] names for `workload` works on,
+/// if it is limited at all. `kafkalog` keeps its log on the one node it
+/// runs on.
+pub fn default_bin_max_nodes(workload: &str) -> Option<usize> {
+    match workload {
+        "kafka" => Some(1),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub ok: usize,
+    pub failed: usize,
+    pub indeterminate: usize,
+}
+
+struct Pending {
+    node: NodeId,
+    request: Value,
+    sent: Duration,
+}
+
+/// Client side of a run: hands out client ids and msg_ids, matches replies to
+/// requests and times requests out. Time is whatever the driver says it is,
+/// so the same bookkeeping serves real and simulated clocks.
+pub struct Clients {
+    clients: Vec<NodeId>,
+    next: usize,
+    id: usize,
+    timeout: Duration,
+    pending: HashMap<(NodeId, usize), Pending>,
+    stats: Stats,
+}
+
+impl Clients {
+    pub fn new(clients: usize, timeout: Duration) -> Self {
+        Self {
+            clients: (1..=clients.max(1) as u32).map(NodeId::client).collect(),
+            next: 0,
+            id: 0,
+            timeout,
+            pending: HashMap::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn request(&mut self, node: NodeId, payload: Value, now: Duration) -> Message<Value> {
+        let client = self.clients[self.next % self.clients.len()];
+        self.next += 1;
+        let id = self.id;
+        self.id += 1;
+        self.pending.insert(
+            (client, id),
+            Pending {
+                node,
+                request: payload.clone(),
+                sent: now,
+            },
+        );
+        Message {
+            src: client,
+            dst: node,
+            body: Body {
+                id: Some(id),
+                in_reply_to: None,
+                payload,
+            },
+        }
+    }
+
+    pub fn receive(&mut self, reply: Message<Value>, workload: &mut dyn Workload) {
+        let Some(id) = reply.body.in_reply_to else {
+            eprintln!("client {} got a message that is not a reply", reply.dst);
+            return;
+        };
+        let Some(pending) = self.pending.remove(&(reply.dst, id)) else {
+            return;
+        };
+        let payload = &reply.body.payload;
+        if payload.get("type").and_then(Value::as_str) == Some("error") {
+            match serde_json::from_value::<ErrorBody>(payload.clone()) {
+                Ok(e) if e.code.is_definite() => {
+                    self.stats.failed += 1;
+                    workload.complete(pending.node, &pending.request, Outcome::Failed(e));
+                }
+                _ => {
+                    self.stats.indeterminate += 1;
+                    workload.complete(pending.node, &pending.request, Outcome::Indeterminate);
+                }
+            }
+        } else {
+            self.stats.ok += 1;
+            workload.complete(pending.node, &pending.request, Outcome::Ok(payload));
+        }
+    }
+
+    pub fn expire(&mut self, now: Duration, workload: &mut dyn Workload) {
+        let timeout = self.timeout;
+        let expired: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| now.saturating_sub(p.sent) >= timeout)
+            .map(|(k, _)| *k)
+            .collect();
+        for key in expired {
+            let pending = self.pending.remove(&key).expect("key was just listed");
+            self.stats.indeterminate += 1;
+            workload.complete(pending.node, &pending.request, Outcome::Indeterminate);
+        }
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+#[derive(Default)]
+pub struct Echo {
+    sent: usize,
+    mismatched: Vec<String>,
+}
+
+impl Workload for Echo {
+    fn name(&self) -> &'static str {
+        "echo"
+    }
+
+    fn generate(&mut self, _rng: &mut StdRng) -> Value {
+        self.sent += 1;
+        json!({"type": "echo", "echo": format!("Please echo {}", self.sent)})
+    }
+
+    fn complete(&mut self, node: NodeId, request: &Value, outcome: Outcome) {
+        if let Outcome::Ok(reply) = outcome {
+            if reply["type"] != "echo_ok" || reply["echo"] != request["echo"] {
+                self.mismatched
+                    .push(format!("{} answered {} with {}", node, request, reply));
+            }
+        }
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        match self.mismatched.first() {
+            Some(first) => anyhow::bail!("{} bad echoes, first: {}", self.mismatched.len(), first),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct UniqueIds {
+    seen: HashSet<String>,
+    duplicates: Vec<String>,
+}
+
+impl Workload for UniqueIds {
+    fn name(&self) -> &'static str {
+        "unique-ids"
+    }
+
+    fn generate(&mut self, _rng: &mut StdRng) -> Value {
+        json!({"type": "generate"})
+    }
+
+    fn complete(&mut self, _node: NodeId, _request: &Value, outcome: Outcome) {
+        if let Outcome::Ok(reply) = outcome {
+            let id = reply["id"].to_string();
+            if !self.seen.insert(id.clone()) {
+                self.duplicates.push(id);
+            }
+        }
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        match self.duplicates.first() {
+            Some(first) => anyhow::bail!(
+                "{} duplicate ids out of {}, first: {}",
+                self.duplicates.len(),
+                self.seen.len() + self.duplicates.len(),
+                first
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Maelstrom's default broadcast topology: nodes laid out row by row on a
+/// square grid, each connected to its horizontal and vertical neighbours.
+pub fn grid_topology(nodes: &[NodeId]) -> BTreeMap<NodeId, Vec<NodeId>> {
+    let width = (nodes.len() as f64).sqrt().ceil().max(1.0) as usize;
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let mut neighbours = Vec::new();
+            if i % width > 0 {
+                neighbours.push(nodes[i - 1]);
+            }
+            if i % width + 1 < width && i + 1 < nodes.len() {
+                neighbours.push(nodes[i + 1]);
+            }
+            if i >= width {
+                neighbours.push(nodes[i - width]);
+            }
+            if i + width < nodes.len() {
+                neighbours.push(nodes[i + width]);
+            }
+            (*node, neighbours)
+        })
+        .collect()
+}
+
+#[derive(Default)]
+pub struct Broadcast {
+    next: u64,
+    attempted: HashSet<u64>,
+    acked: HashSet<u64>,
+    final_reads: Vec<(NodeId, HashSet<u64>)>,
+    finishing: bool,
+    phantom: Vec<u64>,
+}
+
+impl Workload for Broadcast {
+    fn name(&self) -> &'static str {
+        "broadcast"
+    }
+
+    fn setup(&mut self, nodes: &[NodeId]) -> Vec<(NodeId, Value)> {
+        let topology = grid_topology(nodes);
+        nodes
+            .iter()
+            .map(|n| (*n, json!({"type": "topology", "topology": topology})))
+            .collect()
+    }
+
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        if rng.gen_bool(0.2) {
+            return json!({"type": "read"});
+        }
+        let message = self.next;
+        self.next += 1;
+        self.attempted.insert(message);
+        json!({"type": "broadcast", "message": message})
+    }
+
+    fn complete(&mut self, node: NodeId, request: &Value, outcome: Outcome) {
+        let Outcome::Ok(reply) = outcome else {
+            return;
+        };
+        match request["type"].as_str() {
+            Some("broadcast") => {
+                if let Some(message) = request["message"].as_u64() {
+                    self.acked.insert(message);
+                }
+            }
+            Some("read") => {
+                let messages: HashSet<u64> = reply["messages"]
+                    .as_array()
+                    .map(|ms| ms.iter().filter_map(Value::as_u64).collect())
+                    .unwrap_or_default();
+                self.phantom.extend(
+                    messages
+                        .iter()
+                        .filter(|m| !self.attempted.contains(m))
+                        .copied(),
+                );
+                if self.finishing {
+                    self.final_reads.push((node, messages));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self, nodes: &[NodeId]) -> Vec<(NodeId, Value)> {
+        self.finishing = true;
+        nodes
+            .iter()
+            .map(|n| (*n, json!({"type": "read"})))
+            .collect()
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        if let Some(m) = self.phantom.first() {
+            anyhow::bail!("read returned {} which was never broadcast", m);
+        }
+        if self.final_reads.is_empty() {
+            anyhow::bail!("no final read succeeded");
+        }
+        for (node, messages) in &self.final_reads {
+            let lost = self.acked.difference(messages).count();
+            if lost > 0 {
+                anyhow::bail!(
+                    "{} is missing {} of {} acknowledged messages",
+                    node,
+                    lost,
+                    self.acked.len()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct GCounter {
+    acked: i64,
+    /// Sums of the positive and of the negative deltas of indeterminate
+    /// adds, which may or may not have been counted.
+    maybe_up: i64,
+    maybe_down: i64,
+    final_reads: Vec<(NodeId, i64)>,
+    finishing: bool,
+}
+
+impl Workload for GCounter {
+    fn name(&self) -> &'static str {
+        "g-counter"
+    }
+
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        if rng.gen_bool(0.2) {
+            return json!({"type": "read"});
+        }
+        json!({"type": "add", "delta": rng.gen_range(0..5)})
+    }
+
+    fn complete(&mut self, node: NodeId, request: &Value, outcome: Outcome) {
+        match (request["type"].as_str(), outcome) {
+            (Some("add"), Outcome::Ok(_)) => self.acked += request["delta"].as_i64().unwrap_or(0),
+            (Some("add"), Outcome::Indeterminate) => {
+                let delta = request["delta"].as_i64().unwrap_or(0);
+                if delta >= 0 {
+                    self.maybe_up += delta;
+                } else {
+                    self.maybe_down += delta;
+                }
+            }
+            (Some("read"), Outcome::Ok(reply)) if self.finishing => {
+                if let Some(value) = reply["value"].as_i64() {
+                    self.final_reads.push((node, value));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self, nodes: &[NodeId]) -> Vec<(NodeId, Value)> {
+        self.finishing = true;
+        nodes
+            .iter()
+            .map(|n| (*n, json!({"type": "read"})))
+            .collect()
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        if self.final_reads.is_empty() {
+            anyhow::bail!("no final read succeeded");
+        }
+        let lower = self.acked + self.maybe_down;
+        let upper = self.acked + self.maybe_up;
+        for (node, value) in &self.final_reads {
+            if *value < lower || *value > upper {
+                anyhow::bail!(
+                    "{} read {}, expected between {} and {}",
+                    node,
+                    value,
+                    lower,
+                    upper
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The g-counter workload with negative deltas as well.
+#[derive(Default)]
+pub struct PnCounter {
+    counter: GCounter,
+}
+
+impl Workload for PnCounter {
+    fn name(&self) -> &'static str {
+        "pn-counter"
+    }
+
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        if rng.gen_bool(0.2) {
+            return json!({"type": "read"});
+        }
+        json!({"type": "add", "delta": rng.gen_range(-5..5)})
+    }
+
+    fn complete(&mut self, node: NodeId, request: &Value, outcome: Outcome) {
+        self.counter.complete(node, request, outcome)
+    }
+
+    fn finish(&mut self, nodes: &[NodeId]) -> Vec<(NodeId, Value)> {
+        self.counter.finish(nodes)
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        self.counter.check()
+    }
+}
+
+const KAFKA_KEYS: &[&str] = &["k0", "k1", "k2", "k3"];
+
+#[derive(Default)]
+pub struct Kafka {
+    next: u64,
+    sent: HashMap<String, BTreeMap<u64, u64>>,
+    polled: HashMap<String, u64>,
+    violations: Vec<String>,
+}
+
+impl Workload for Kafka {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        let key = KAFKA_KEYS[rng.gen_range(0..KAFKA_KEYS.len())];
+        match rng.gen_range(0..10) {
+            0..=4 => {
+                let msg = self.next;
+                self.next += 1;
+                json!({"type": "send", "key": key, "msg": msg})
+            }
+            5..=7 => {
+                let from = self.polled.get(key).copied().unwrap_or(0);
+                json!({"type": "poll", "offsets": {key: from}})
+            }
+            8 => {
+                let upto = self.polled.get(key).copied().unwrap_or(0);
+                json!({"type": "commit_offsets", "offsets": {key: upto}})
+            }
+            _ => json!({"type": "list_committed_offsets", "keys": [key]}),
+        }
+    }
+
+    fn complete(&mut self, _node: NodeId, request: &Value, outcome: Outcome) {
+        let Outcome::Ok(reply) = outcome else {
+            return;
+        };
+        match request["type"].as_str() {
+            Some("send") => {
+                let key = request["key"].as_str().unwrap_or_default().to_string();
+                let (Some(msg), Some(offset)) = (request["msg"].as_u64(), reply["offset"].as_u64())
+                else {
+                    self.violations.push(format!("bad send_ok {}", reply));
+                    return;
+                };
+                let log = self.sent.entry(key.clone()).or_default();
+                if let Some(other) = log.insert(offset, msg) {
+                    self.violations.push(format!(
+                        "{} offset {} given to both {} and {}",
+                        key, offset, other, msg
+                    ));
+                }
+            }
+            Some("poll") => {
+                let Some(msgs) = reply["msgs"].as_object() else {
+                    return;
+                };
+                for (key, entries) in msgs {
+                    for entry in entries.as_array().into_iter().flatten() {
+                        let (Some(offset), Some(msg)) = (entry[0].as_u64(), entry[1].as_u64())
+                        else {
+                            continue;
+                        };
+                        let known = self.sent.get(key).and_then(|log| log.get(&offset));
+                        if let Some(&expected) = known {
+                            if expected != msg {
+                                self.violations.push(format!(
+                                    "poll of {} at {} returned {}, but {} was sent there",
+                                    key, offset, msg, expected
+                                ));
+                            }
+                        }
+                        let next = self.polled.entry(key.clone()).or_default();
+                        *next = (*next).max(offset + 1);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        match self.violations.first() {
+            Some(first) => anyhow::bail!("{} violations, first: {}", self.violations.len(), first),
+            None => Ok(()),
+        }
+    }
+}
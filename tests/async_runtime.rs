@@ -0,0 +1,140 @@
+//! Runs the `async-kv-counter` bin over its stdin and stdout, with the test
+//! playing both the client and `lin-kv`.
+#![cfg(feature = "async")]
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+struct Maelstrom {
+    node: Child,
+    stdin: Option<ChildStdin>,
+    lines: Receiver<String>,
+}
+
+impl Maelstrom {
+    fn start() -> Self {
+        let mut node = Command::new(env!("CARGO_BIN_EXE_async-kv-counter"))
+            .env("RUSTENGAN_STRICT", "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("node starts");
+        let stdout = node.stdout.take().expect("piped stdout");
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            stdin: node.stdin.take(),
+            node,
+            lines,
+        }
+    }
+
+    fn send(&mut self, src: &str, body: Value) {
+        let msg = json!({ "src": src, "dest": "n1", "body": body });
+        let stdin = self.stdin.as_mut().expect("stdin still open");
+        writeln!(stdin, "{}", msg).expect("node reads its input");
+    }
+
+    fn init(&mut self) {
+        self.send(
+            "c0",
+            json!({ "type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"] }),
+        );
+    }
+
+    /// The next message the node sends, checked to be going to `dst`.
+    fn recv(&mut self, dst: &str) -> Value {
+        let line = self
+            .lines
+            .recv_timeout(Duration::from_secs(5))
+            .expect("node sends a message");
+        let msg: Value = serde_json::from_str(&line).expect("node sends JSON");
+        assert_eq!(msg["dest"], dst, "{}", msg);
+        msg["body"].clone()
+    }
+
+    /// Answers the `lin-kv` request `request` with `body`.
+    fn answer(&mut self, request: &Value, mut body: Value) {
+        body["in_reply_to"] = request["msg_id"].clone();
+        self.send("lin-kv", body);
+    }
+}
+
+#[test]
+fn requests_sent_before_init_are_handled_after_it() {
+    let mut maelstrom = Maelstrom::start();
+    maelstrom.send("c1", json!({ "type": "read", "msg_id": 1 }));
+    maelstrom.init();
+    assert_eq!(maelstrom.recv("c0")["type"], "init_ok");
+
+    let read = maelstrom.recv("lin-kv");
+    assert_eq!(read["type"], "read");
+    assert_eq!(read["key"], "counter");
+    maelstrom.answer(
+        &read,
+        json!({ "type": "error", "code": 20, "text": "no counter" }),
+    );
+    let reply = maelstrom.recv("c1");
+    assert_eq!(reply["type"], "read_ok");
+    assert_eq!(reply["value"], 0);
+    assert_eq!(reply["in_reply_to"], 1);
+}
+
+#[test]
+fn add_starts_over_when_its_cas_loses() {
+    let mut maelstrom = Maelstrom::start();
+    maelstrom.init();
+    maelstrom.recv("c0");
+    maelstrom.send("c1", json!({ "type": "add", "msg_id": 2, "delta": 3 }));
+
+    let read = maelstrom.recv("lin-kv");
+    maelstrom.answer(&read, json!({ "type": "read_ok", "value": 5 }));
+    let cas = maelstrom.recv("lin-kv");
+    assert_eq!(
+        (&cas["type"], &cas["from"], &cas["to"]),
+        (&json!("cas"), &json!(5), &json!(8))
+    );
+    maelstrom.answer(
+        &cas,
+        json!({ "type": "error", "code": 22, "text": "not 5" }),
+    );
+
+    let read = maelstrom.recv("lin-kv");
+    maelstrom.answer(&read, json!({ "type": "read_ok", "value": 6 }));
+    let cas = maelstrom.recv("lin-kv");
+    assert_eq!((&cas["from"], &cas["to"]), (&json!(6), &json!(9)));
+    maelstrom.answer(&cas, json!({ "type": "cas_ok" }));
+
+    let reply = maelstrom.recv("c1");
+    assert_eq!(reply["type"], "add_ok");
+    assert_eq!(reply["in_reply_to"], 2);
+}
+
+#[test]
+fn requests_in_flight_at_eof_are_still_answered() {
+    let mut maelstrom = Maelstrom::start();
+    maelstrom.init();
+    maelstrom.recv("c0");
+    maelstrom.send("c1", json!({ "type": "read", "msg_id": 3 }));
+    maelstrom.recv("lin-kv");
+    // No answer from lin-kv can come in anymore: the read times out.
+    maelstrom.stdin = None;
+
+    let reply = maelstrom.recv("c1");
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], 11);
+    let status = maelstrom.node.wait().expect("node exits");
+    assert!(status.success());
+}
@@ -0,0 +1,122 @@
+use rustengan::{Cluster, Init, InitError, NodeId};
+use std::collections::{BTreeMap, HashSet};
+
+fn servers(count: u32) -> Vec<NodeId> {
+    (0..count).map(NodeId::server).collect()
+}
+
+fn cluster(me: u32, node_ids: Vec<NodeId>) -> Cluster {
+    Cluster::new(&Init {
+        node_id: NodeId::server(me),
+        node_ids,
+    })
+    .expect("a member")
+}
+
+#[test]
+fn majority() {
+    let expected = [(1, 1), (2, 2), (3, 2), (4, 3), (5, 3)];
+    for (count, majority) in expected {
+        assert_eq!(
+            cluster(0, servers(count)).majority(),
+            majority,
+            "{} nodes",
+            count
+        );
+    }
+}
+
+#[test]
+fn first_node_leads() {
+    let nodes = vec![NodeId::server(2), NodeId::server(0), NodeId::server(1)];
+    for me in 0..3 {
+        let cluster = cluster(me, nodes.clone());
+        assert_eq!(cluster.leader(), NodeId::server(0));
+        assert_eq!(cluster.is_leader(), me == 0);
+    }
+}
+
+#[test]
+fn peers_leave_out_self() {
+    let cluster = cluster(1, servers(3));
+    assert_eq!(cluster.me(), NodeId::server(1));
+    assert_eq!(cluster.index(), 1);
+    assert_eq!(cluster.nodes(), servers(3));
+    let peers: Vec<_> = cluster.peers().collect();
+    assert_eq!(peers, [NodeId::server(0), NodeId::server(2)]);
+    assert_eq!(cluster.index_of(NodeId::server(2)), Some(2));
+    assert!(!cluster.contains(NodeId::server(3)));
+}
+
+#[test]
+fn duplicate_ids_count_once() {
+    let cluster = cluster(
+        0,
+        vec![NodeId::server(0), NodeId::server(1), NodeId::server(0)],
+    );
+    assert_eq!(cluster.len(), 2);
+    assert_eq!(cluster.majority(), 2);
+}
+
+#[test]
+fn unknown_node_id_is_an_error() {
+    let result = Cluster::new(&Init {
+        node_id: NodeId::server(7),
+        node_ids: servers(3),
+    });
+    assert!(matches!(
+        result,
+        Err(InitError::UnknownNode { node_id, .. }) if node_id == NodeId::server(7)
+    ));
+}
+
+#[test]
+fn placement_does_not_depend_on_node_order() {
+    let sorted = cluster(0, servers(5));
+    let mut reversed = servers(5);
+    reversed.reverse();
+    let reversed = cluster(3, reversed);
+    let shuffled = cluster(4, [3, 0, 4, 1, 2].into_iter().map(NodeId::server).collect());
+    for i in 0..200 {
+        let key = format!("key-{}", i);
+        let owner = sorted.owner(&key);
+        assert_eq!(reversed.owner(&key), owner, "{}", key);
+        assert_eq!(shuffled.owner(&key), owner, "{}", key);
+        let replicas = sorted.replicas(&key, 3);
+        assert_eq!(reversed.replicas(&key, 3), replicas, "{}", key);
+        assert_eq!(shuffled.replicas(&key, 3), replicas, "{}", key);
+        assert_eq!(replicas[0], owner);
+        assert_eq!(shuffled.owns(&key), owner == NodeId::server(4));
+    }
+}
+
+#[test]
+fn replicas_are_distinct_and_wrap_around() {
+    let cluster = cluster(0, servers(5));
+    // Enough keys that some land near the end of the ring, from where
+    // finding all five nodes takes wrapping around to its start.
+    for i in 0..5000 {
+        let key = format!("key-{}", i);
+        // Asking for more nodes than there are gives each one once.
+        let replicas = cluster.replicas(&key, 10);
+        let distinct: HashSet<_> = replicas.iter().collect();
+        assert_eq!(distinct.len(), 5, "{}: {:?}", key, replicas);
+        assert_eq!(replicas.len(), 5);
+        // Longer lists extend shorter ones.
+        assert_eq!(cluster.replicas(&key, 2), replicas[..2]);
+    }
+}
+
+#[test]
+fn keys_spread_over_the_nodes() {
+    let cluster = cluster(0, servers(5));
+    let mut owned: BTreeMap<NodeId, usize> = BTreeMap::new();
+    for i in 0..5000 {
+        *owned
+            .entry(cluster.owner(&format!("key-{}", i)))
+            .or_default() += 1;
+    }
+    for (node, count) in owned {
+        assert!((500..=1800).contains(&count), "{} owns {}", node, count);
+    }
+}
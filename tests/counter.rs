@@ -0,0 +1,102 @@
+use rustengan::counter::{Counter, CounterNode, InjectedPayload, Payload};
+use rustengan::crdt::{GCounter, PNCounter};
+use rustengan::faults::Faults;
+use rustengan::sim::{RunOptions, Simulation};
+use rustengan::workload;
+use rustengan::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Duration;
+
+type Sim<C> = Simulation<(), CounterNode<C>, Payload<C>, InjectedPayload>;
+
+/// What a counter needs to go over the simulated wire.
+trait Replicated: Counter + Serialize + DeserializeOwned + Send + 'static {}
+
+impl<C: Counter + Serialize + DeserializeOwned + Send + 'static> Replicated for C {}
+
+fn lossy() -> Faults {
+    Faults {
+        drop: 0.3,
+        duplicate: 0.1,
+        ..Faults::default()
+    }
+}
+
+fn request<C: Replicated>(
+    sim: &mut Sim<C>,
+    id: usize,
+    node: NodeId,
+    payload: Payload<C>,
+) -> anyhow::Result<()> {
+    sim.send(&Message {
+        src: NodeId::client(1),
+        dst: node,
+        body: Body {
+            id: Some(id),
+            in_reply_to: None,
+            payload,
+        },
+    })
+}
+
+#[test]
+fn pn_counter_converges_with_negative_deltas() -> anyhow::Result<()> {
+    let mut sim = Sim::<PNCounter>::new(21, 5, ())?.with_faults(lossy());
+    let nodes = sim.node_ids().to_vec();
+    let mut total = 0;
+    for id in 0..60 {
+        // Mostly down, so the count ends up below zero.
+        let delta = id as i64 % 7 - 4;
+        total += delta;
+        request(
+            &mut sim,
+            id,
+            nodes[id % nodes.len()],
+            Payload::Add { delta },
+        )?;
+        sim.run_for(Duration::from_millis(20))?;
+    }
+    assert!(total < 0);
+    sim.run_for(Duration::from_secs(10))?;
+    sim.take_client_messages();
+
+    for (i, &node) in nodes.iter().enumerate() {
+        request(&mut sim, 100 + i, node, Payload::Read)?;
+    }
+    sim.run_for(Duration::from_secs(1))?;
+    let replies = sim.take_client_messages();
+    assert!(!replies.is_empty());
+    for reply in replies {
+        assert_eq!(reply.body.payload["type"], "read_ok");
+        assert_eq!(
+            reply.body.payload["value"], total,
+            "read from {}",
+            reply.src
+        );
+    }
+    Ok(())
+}
+
+fn passes_workload<C: Replicated>(name: &str) -> anyhow::Result<()> {
+    let mut sim = Sim::<C>::new(22, 3, ())?.with_faults(lossy());
+    let mut workload = workload::by_name(name).expect("a known workload");
+    let options = RunOptions {
+        time_limit: Duration::from_secs(5),
+        recovery: Duration::from_secs(5),
+        ..RunOptions::default()
+    };
+    let stats = sim.run_workload(workload.as_mut(), options)?;
+    assert!(stats.ok > 0);
+    workload.check()
+}
+
+#[test]
+fn pn_counter_passes_its_workload() -> anyhow::Result<()> {
+    passes_workload::<PNCounter>("pn-counter")
+}
+
+#[test]
+fn g_counter_passes_its_workload() -> anyhow::Result<()> {
+    passes_workload::<GCounter>("g-counter")
+}
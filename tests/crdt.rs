@@ -0,0 +1,340 @@
+use proptest::prelude::*;
+use rustengan::crdt::*;
+use rustengan::NodeId;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+
+// Few nodes and few values, so that generated replicas overlap.
+
+fn node() -> impl Strategy<Value = NodeId> {
+    (0u32..3).prop_map(NodeId::server)
+}
+
+fn stamp() -> impl Strategy<Value = Stamp> {
+    (0u64..4, node()).prop_map(|(time, node)| Stamp::new(time, node))
+}
+
+fn g_counter() -> impl Strategy<Value = GCounter> {
+    prop::collection::vec((node(), 0u64..5), 0..8).prop_map(|ops| {
+        let mut counter = GCounter::new();
+        for (node, by) in ops {
+            counter.increment(node, by);
+        }
+        counter
+    })
+}
+
+fn pn_counter() -> impl Strategy<Value = PNCounter> {
+    prop::collection::vec((node(), -5i64..5), 0..8).prop_map(|ops| {
+        let mut counter = PNCounter::new();
+        for (node, delta) in ops {
+            counter.add(node, delta);
+        }
+        counter
+    })
+}
+
+fn g_set() -> impl Strategy<Value = GSet<u8>> {
+    prop::collection::btree_set(0u8..6, 0..6).prop_map(GSet::from_iter)
+}
+
+fn two_p_set() -> impl Strategy<Value = TwoPSet<u8>> {
+    prop::collection::vec((any::<bool>(), 0u8..6), 0..10).prop_map(|ops| {
+        let mut set = TwoPSet::new();
+        for (insert, value) in ops {
+            if insert {
+                set.insert(value);
+            } else {
+                set.remove(&value);
+            }
+        }
+        set
+    })
+}
+
+fn or_set() -> impl Strategy<Value = ORSet<u8>> {
+    prop::collection::vec((any::<bool>(), node(), 0u8..6), 0..10).prop_map(|ops| {
+        let mut set = ORSet::new();
+        for (insert, node, value) in ops {
+            if insert {
+                set.insert(node, value);
+            } else {
+                set.remove(&value);
+            }
+        }
+        set
+    })
+}
+
+fn lww_register() -> impl Strategy<Value = LwwRegister<u8>> {
+    prop::collection::vec((0u8..6, stamp()), 0..4).prop_map(|writes| {
+        let mut register = LwwRegister::new();
+        for (value, stamp) in writes {
+            register.set(value, stamp);
+        }
+        register
+    })
+}
+
+fn lww_map() -> impl Strategy<Value = LwwMap<String, u8>> {
+    let op = (any::<bool>(), 0u8..4, 0u8..6, stamp());
+    prop::collection::vec(op, 0..10).prop_map(|ops| {
+        let mut map = LwwMap::new();
+        for (insert, key, value, stamp) in ops {
+            let key = format!("k{}", key);
+            if insert {
+                map.insert(key, value, stamp);
+            } else {
+                map.remove(key, stamp);
+            }
+        }
+        map
+    })
+}
+
+fn round_trips<T>(state: &T) -> Result<(), TestCaseError>
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let json = serde_json::to_string(state).expect("serialize");
+    let back: T = serde_json::from_str(&json).expect("deserialize");
+    prop_assert_eq!(&back, state);
+    Ok(())
+}
+
+macro_rules! lattice_laws {
+    ($name:ident, $strategy:expr) => {
+        mod $name {
+            use super::*;
+
+            proptest! {
+                #[test]
+                fn commutative(a in $strategy, b in $strategy) {
+                    prop_assert_eq!(a.clone().merged(&b), b.merged(&a));
+                }
+
+                #[test]
+                fn associative(a in $strategy, b in $strategy, c in $strategy) {
+                    let left = a.clone().merged(&b).merged(&c);
+                    let right = a.merged(&b.merged(&c));
+                    prop_assert_eq!(left, right);
+                }
+
+                #[test]
+                fn idempotent(a in $strategy) {
+                    prop_assert_eq!(a.clone().merged(&a), a);
+                }
+
+                #[test]
+                fn serde_round_trip(a in $strategy) {
+                    round_trips(&a)?;
+                }
+
+                #[test]
+                fn delta_fills_the_gap(a in $strategy, known in $strategy) {
+                    let delta = a.delta_since(&known);
+                    prop_assert_eq!(known.clone().merged(&delta), known.merged(&a));
+                }
+
+                #[test]
+                fn no_delta_for_what_is_known(a in $strategy, b in $strategy) {
+                    let known = a.clone().merged(&b);
+                    prop_assert_eq!(a.delta_since(&known), Default::default());
+                }
+            }
+        }
+    };
+}
+
+lattice_laws!(g_counter_laws, g_counter());
+lattice_laws!(pn_counter_laws, pn_counter());
+lattice_laws!(g_set_laws, g_set());
+lattice_laws!(two_p_set_laws, two_p_set());
+lattice_laws!(or_set_laws, or_set());
+lattice_laws!(lww_register_laws, lww_register());
+lattice_laws!(lww_map_laws, lww_map());
+
+proptest! {
+    #[test]
+    fn g_counter_merge_keeps_every_increment(a in g_counter(), b in g_counter()) {
+        let merged = a.clone().merged(&b);
+        for node in (0..3).map(NodeId::server) {
+            prop_assert_eq!(merged.get(node), a.get(node).max(b.get(node)));
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    InsertAt(bool, u8),
+    /// A sync from one replica to the other, lost unless `delivered`.
+    Sync {
+        from_a: bool,
+        delivered: bool,
+    },
+}
+
+fn steps() -> impl Strategy<Value = Vec<Step>> {
+    let step = prop_oneof![
+        (any::<bool>(), 0u8..20).prop_map(|(at_a, value)| Step::InsertAt(at_a, value)),
+        (any::<bool>(), any::<bool>())
+            .prop_map(|(from_a, delivered)| Step::Sync { from_a, delivered }),
+    ];
+    prop::collection::vec(step, 0..40)
+}
+
+fn sync(from: &mut Replicator<GSet<u8>>, to: &mut Replicator<GSet<u8>>, ids: (NodeId, NodeId)) {
+    let (from_id, to_id) = ids;
+    if let Some(sync) = from.sync_for(to_id) {
+        to.receive(from_id, &sync);
+    }
+}
+
+proptest! {
+    #[test]
+    fn replicators_converge_despite_lost_syncs(steps in steps()) {
+        let (a_id, b_id) = (NodeId::server(0), NodeId::server(1));
+        let mut a = Replicator::new(GSet::new());
+        let mut b = Replicator::new(GSet::new());
+        for step in steps {
+            match step {
+                Step::InsertAt(true, value) => {
+                    a.state_mut().insert(value);
+                }
+                Step::InsertAt(false, value) => {
+                    b.state_mut().insert(value);
+                }
+                Step::Sync { from_a: true, delivered } => {
+                    let sync = a.sync_for(b_id);
+                    if let (Some(sync), true) = (sync, delivered) {
+                        b.receive(a_id, &sync);
+                    }
+                }
+                Step::Sync { from_a: false, delivered } => {
+                    let sync = b.sync_for(a_id);
+                    if let (Some(sync), true) = (sync, delivered) {
+                        a.receive(b_id, &sync);
+                    }
+                }
+            }
+        }
+        for _ in 0..3 {
+            sync(&mut a, &mut b, (a_id, b_id));
+            sync(&mut b, &mut a, (b_id, a_id));
+        }
+        prop_assert_eq!(a.state(), b.state());
+        // Everything is acknowledged, so there is nothing left to send.
+        prop_assert!(a.sync_for(b_id).is_none());
+        prop_assert!(b.sync_for(a_id).is_none());
+    }
+}
+
+#[test]
+fn replicator_sends_only_what_was_not_acknowledged() {
+    let (a_id, b_id) = (NodeId::server(0), NodeId::server(1));
+    let mut a = Replicator::new(GSet::new());
+    let mut b = Replicator::new(GSet::new());
+    a.state_mut().insert(1);
+    let first = a.sync_for(b_id).expect("1 is new");
+    b.receive(a_id, &first);
+    let ack = b.sync_for(a_id).expect("b owes an ack");
+    assert_eq!(ack.delta, None);
+    a.receive(b_id, &ack);
+
+    a.state_mut().insert(2);
+    let second = a.sync_for(b_id).expect("2 is new");
+    let (_, delta) = second.delta.expect("has a delta");
+    assert_eq!(delta, GSet::from_iter([2]));
+}
+
+#[test]
+fn stale_g_counter_does_not_overwrite_newer_count() {
+    let n0 = NodeId::server(0);
+    let mut stale = GCounter::new();
+    stale.increment(n0, 1);
+    let mut current = stale.clone();
+    current.increment(n0, 4);
+
+    current.merge(&stale);
+    assert_eq!(current.value(), 5);
+}
+
+#[test]
+fn pn_counter_counts_both_ways() {
+    let (n0, n1) = (NodeId::server(0), NodeId::server(1));
+    let mut a = PNCounter::new();
+    a.add(n0, 5);
+    let mut b = PNCounter::new();
+    b.add(n1, -7);
+    assert_eq!(a.merged(&b).value(), -2);
+}
+
+#[test]
+fn two_p_set_removal_is_final() {
+    let mut set = TwoPSet::new();
+    set.insert(1);
+    assert!(set.remove(&1));
+    set.insert(1);
+    assert!(!set.contains(&1));
+}
+
+#[test]
+fn or_set_insert_wins_over_concurrent_remove() {
+    let (n0, n1) = (NodeId::server(0), NodeId::server(1));
+    let mut a = ORSet::new();
+    a.insert(n0, "x");
+    let mut b = a.clone();
+
+    assert!(a.remove(&"x"));
+    b.insert(n1, "x");
+
+    let merged = a.merged(&b);
+    assert!(merged.contains(&"x"));
+    assert_eq!(merged.iter().count(), 1);
+}
+
+#[test]
+fn or_set_remove_takes_out_observed_inserts() {
+    let (n0, n1) = (NodeId::server(0), NodeId::server(1));
+    let mut a = ORSet::new();
+    a.insert(n0, "x");
+    let mut b = ORSet::new();
+    b.insert(n1, "x");
+    a.merge(&b);
+
+    assert!(a.remove(&"x"));
+    assert!(!a.merged(&b).contains(&"x"));
+}
+
+#[test]
+fn lww_register_keeps_the_latest_write() {
+    let (n0, n1) = (NodeId::server(0), NodeId::server(1));
+    let mut a = LwwRegister::new();
+    a.set("old", Stamp::new(1, n1));
+    let mut b = LwwRegister::new();
+    b.set("new", Stamp::new(2, n0));
+
+    assert_eq!(a.clone().merged(&b).get(), Some(&"new"));
+    assert_eq!(b.merged(&a).get(), Some(&"new"));
+}
+
+#[test]
+fn lww_map_remove_loses_to_later_insert() {
+    let n0 = NodeId::server(0);
+    let mut a = LwwMap::new();
+    a.insert("k", 1, Stamp::new(1, n0));
+    let mut b = a.clone();
+    a.remove("k", Stamp::new(2, n0));
+    b.insert("k", 2, Stamp::new(3, n0));
+
+    let merged = a.merged(&b);
+    assert_eq!(merged.get(&"k"), Some(&2));
+}
+
+#[test]
+fn g_counter_serializes_as_counts_by_node() {
+    let mut counter = GCounter::new();
+    counter.increment(NodeId::server(1), 3);
+    assert_eq!(serde_json::to_string(&counter).unwrap(), r#"{"n1":3}"#);
+}
@@ -0,0 +1,155 @@
+use rustengan::sim::Simulation;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Add { delta: u64 },
+    AddOk,
+}
+
+struct AddNode;
+
+impl Node<(), Payload> for AddNode {
+    fn from_init(_state: (), _init: Init, _tx: Sender<Event<Payload>>) -> anyhow::Result<Self> {
+        Ok(AddNode)
+    }
+
+    fn step(&mut self, input: Event<Payload>, outbox: &mut Outbox<()>) -> anyhow::Result<()> {
+        if let Event::Message(input) = input {
+            if let Payload::Add { .. } = input.body.payload {
+                outbox.reply(&input, Payload::AddOk)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn sim(strict: bool) -> Simulation<(), AddNode, Payload, ()> {
+    Simulation::new(1, 1, ())
+        .expect("nodes initialize")
+        .with_strict(strict)
+}
+
+fn request(body: Value) -> Message<Value> {
+    Message {
+        src: NodeId::client(1),
+        dst: NodeId::server(0),
+        body: Body {
+            id: Some(7),
+            in_reply_to: None,
+            payload: body,
+        },
+    }
+}
+
+/// Sends `body` as a request and returns the one reply it gets.
+fn reply_to(body: Value) -> anyhow::Result<Message<Value>> {
+    let mut sim = sim(false);
+    sim.send(&request(body))?;
+    sim.run_for(Duration::from_secs(1))?;
+    let mut replies = sim.take_client_messages();
+    assert_eq!(replies.len(), 1, "{:?}", replies);
+    let reply = replies.remove(0);
+    assert_eq!(reply.body.in_reply_to, Some(7));
+    Ok(reply)
+}
+
+fn error_code(reply: &Message<Value>) -> ErrorCode {
+    let ErrorPayload::Error(error) =
+        serde_json::from_value(reply.body.payload.clone()).expect("an error reply");
+    error.code
+}
+
+#[test]
+fn known_type_is_handled() -> anyhow::Result<()> {
+    let reply = reply_to(json!({"type": "add", "delta": 3}))?;
+    assert_eq!(reply.body.payload["type"], "add_ok");
+    Ok(())
+}
+
+#[test]
+fn unknown_type_is_not_supported() -> anyhow::Result<()> {
+    let reply = reply_to(json!({"type": "subtract", "delta": 3}))?;
+    assert_eq!(error_code(&reply), ErrorCode::NotSupported);
+    Ok(())
+}
+
+#[test]
+fn bad_body_of_known_type_is_malformed() -> anyhow::Result<()> {
+    let reply = reply_to(json!({"type": "add", "delta": -3}))?;
+    assert_eq!(error_code(&reply), ErrorCode::MalformedRequest);
+    let reply = reply_to(json!({"type": "add"}))?;
+    assert_eq!(error_code(&reply), ErrorCode::MalformedRequest);
+    Ok(())
+}
+
+#[test]
+fn broken_json_is_dropped() -> anyhow::Result<()> {
+    let mut sim = sim(false);
+    let (client, node) = (NodeId::client(1), NodeId::server(0));
+    sim.send_line(client, node, r#"{"src":"c1","dest":"n0","body":{"type":"#);
+    sim.run_for(Duration::from_secs(1))?;
+    assert!(sim.take_client_messages().is_empty());
+    assert!(sim
+        .logs()
+        .iter()
+        .any(|(_, _, line)| line.starts_with("dropping malformed input")));
+
+    // The node is still there.
+    sim.send(&request(json!({"type": "add", "delta": 1})))?;
+    sim.run_for(Duration::from_secs(1))?;
+    assert_eq!(sim.take_client_messages().len(), 1);
+    Ok(())
+}
+
+#[test]
+fn late_reply_is_dropped_without_answer() -> anyhow::Result<()> {
+    let mut sim = sim(true);
+    let mut reply = request(json!({"type": "read_ok"}));
+    reply.body.id = None;
+    reply.body.in_reply_to = Some(3);
+    sim.send(&reply)?;
+    sim.run_for(Duration::from_secs(1))?;
+    assert!(sim.take_client_messages().is_empty());
+    Ok(())
+}
+
+#[test]
+fn strict_mode_fails_on_undecodable_input() -> anyhow::Result<()> {
+    let mut unknown = sim(true);
+    unknown.send(&request(json!({"type": "subtract", "delta": 3})))?;
+    assert!(unknown.run_for(Duration::from_secs(1)).is_err());
+
+    let mut bad_body = sim(true);
+    bad_body.send(&request(json!({"type": "add", "delta": -3})))?;
+    assert!(bad_body.run_for(Duration::from_secs(1)).is_err());
+
+    let mut broken = sim(true);
+    broken.send_line(NodeId::client(1), NodeId::server(0), "not json");
+    assert!(broken.run_for(Duration::from_secs(1)).is_err());
+    Ok(())
+}
+
+#[test]
+fn second_init_is_malformed() -> anyhow::Result<()> {
+    // Strict mode does not change that.
+    for strict in [false, true] {
+        let mut sim = sim(strict);
+        sim.send(&request(json!({
+            "type": "init",
+            "node_id": "n0",
+            "node_ids": ["n0"],
+        })))?;
+        sim.run_for(Duration::from_secs(1))?;
+        let replies = sim.take_client_messages();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(error_code(&replies[0]), ErrorCode::MalformedRequest);
+    }
+    Ok(())
+}
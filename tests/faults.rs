@@ -0,0 +1,158 @@
+use rustengan::faults::{Faults, Network, PartitionKind, PartitionSchedule};
+use rustengan::NodeId;
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+fn servers(n: usize) -> Vec<NodeId> {
+    (0..n as u32).map(NodeId::server).collect()
+}
+
+fn partitioned(kind: PartitionKind) -> Network {
+    Network::new(
+        Faults {
+            partition: Some(PartitionSchedule {
+                kind,
+                interval: Duration::from_secs(1),
+            }),
+            ..Faults::default()
+        },
+        7,
+    )
+}
+
+/// Who `node` can send to.
+fn reach(network: &mut Network, nodes: &[NodeId], node: NodeId) -> BTreeSet<NodeId> {
+    nodes
+        .iter()
+        .copied()
+        .filter(|&dst| !network.deliveries(node, dst).is_empty())
+        .collect()
+}
+
+/// The distinct sets of nodes the nodes can each reach.
+fn shape(network: &mut Network, nodes: &[NodeId]) -> BTreeSet<BTreeSet<NodeId>> {
+    nodes
+        .iter()
+        .map(|&node| reach(network, nodes, node))
+        .collect()
+}
+
+#[test]
+fn link_drop_overrides_drop_one_way() {
+    let (a, b, c) = (NodeId::server(0), NodeId::server(1), NodeId::server(2));
+    let mut faults = Faults::default();
+    faults.link_drop.insert((a, b), 1.0);
+    let mut network = Network::new(faults, 1);
+    for _ in 0..100 {
+        assert!(network.deliveries(a, b).is_empty());
+        assert_eq!(network.deliveries(b, a).len(), 1);
+        assert_eq!(network.deliveries(a, c).len(), 1);
+    }
+
+    let mut faults = Faults {
+        drop: 1.0,
+        ..Faults::default()
+    };
+    faults.link_drop.insert((a, b), 0.0);
+    let mut network = Network::new(faults, 1);
+    for _ in 0..100 {
+        assert_eq!(network.deliveries(a, b).len(), 1);
+        assert!(network.deliveries(b, a).is_empty());
+    }
+}
+
+#[test]
+fn link_drop_leaves_clients_alone() {
+    let mut faults = Faults::default();
+    let (client, server) = (NodeId::client(1), NodeId::server(0));
+    faults.link_drop.insert((client, server), 1.0);
+    let mut network = Network::new(faults, 1);
+    assert_eq!(network.deliveries(client, server).len(), 1);
+}
+
+#[test]
+fn halves_split_evenly() {
+    for n in [4, 5] {
+        let nodes = servers(n);
+        let mut network = partitioned(PartitionKind::RandomHalves);
+        network.advance(Duration::from_secs(1), &nodes);
+        let mut sizes: Vec<usize> = shape(&mut network, &nodes)
+            .iter()
+            .map(BTreeSet::len)
+            .collect();
+        sizes.sort();
+        assert_eq!(sizes, [n / 2, n - n / 2], "{} nodes", n);
+    }
+}
+
+#[test]
+fn majority_keeps_a_strict_majority_together() {
+    for n in [3, 4, 5] {
+        let nodes = servers(n);
+        let mut network = partitioned(PartitionKind::MajorityMinority);
+        network.advance(Duration::from_secs(1), &nodes);
+        let mut sizes: Vec<usize> = shape(&mut network, &nodes)
+            .iter()
+            .map(BTreeSet::len)
+            .collect();
+        sizes.sort();
+        assert_eq!(sizes, [(n - 1) / 2, n / 2 + 1], "{} nodes", n);
+    }
+}
+
+#[test]
+fn bridge_reaches_both_sides() {
+    let nodes = servers(5);
+    let mut network = partitioned(PartitionKind::Bridge);
+    network.advance(Duration::from_secs(1), &nodes);
+    let bridges: Vec<NodeId> = nodes
+        .iter()
+        .copied()
+        .filter(|&node| reach(&mut network, &nodes, node).len() == nodes.len())
+        .collect();
+    assert_eq!(bridges.len(), 1);
+
+    // Everyone else reaches their own side and the bridge only.
+    let sides: BTreeSet<BTreeSet<NodeId>> = shape(&mut network, &nodes)
+        .into_iter()
+        .filter(|side| side.len() < nodes.len())
+        .collect();
+    assert_eq!(sides.len(), 2);
+    for side in &sides {
+        assert_eq!(side.len(), 3);
+        assert!(side.contains(&bridges[0]));
+    }
+}
+
+#[test]
+fn advance_alternates_between_healed_and_partitioned() {
+    let nodes = servers(4);
+    let mut network = partitioned(PartitionKind::RandomHalves);
+    let secs = Duration::from_secs_f64;
+
+    assert_eq!(network.advance(secs(0.5), &nodes), None);
+    assert!(!network.is_partitioned());
+
+    let change = network.advance(secs(1.0), &nodes).expect("a partition");
+    assert!(change.starts_with("partitioned network into"), "{}", change);
+    assert!(network.is_partitioned());
+    assert_eq!(network.advance(secs(1.5), &nodes), None);
+
+    assert_eq!(
+        network.advance(secs(2.0), &nodes).as_deref(),
+        Some("healed network")
+    );
+    assert!(!network.is_partitioned());
+
+    // Skipping a heal and a partition at once leaves the network as the
+    // last one left it.
+    assert!(network.advance(secs(3.0), &nodes).is_some());
+    let change = network.advance(secs(5.2), &nodes).expect("a partition");
+    assert!(change.starts_with("partitioned network into"), "{}", change);
+    assert!(network.is_partitioned());
+
+    // Healing for good stops the schedule.
+    assert!(network.heal());
+    assert_eq!(network.advance(secs(60.0), &nodes), None);
+    assert!(!network.is_partitioned());
+}
@@ -0,0 +1,140 @@
+use rustengan::crdt::{GSet, Sync};
+use rustengan::faults::Faults;
+use rustengan::gossip::{AllPeers, Gossip, Neighbors, PeerSelection, RandomPeers};
+use rustengan::sim::Simulation;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Add { value: u32 },
+    Gossip(Sync<GSet<u32>>),
+}
+
+#[derive(Clone)]
+struct Round;
+
+/// A whole replicated workload: a set every node adds to.
+struct SetNode<S> {
+    gossip: Gossip<GSet<u32>, Payload, S>,
+}
+
+/// How each node picks who to gossip with.
+trait Selection: PeerSelection + Sized {
+    fn for_node(cluster: &Cluster) -> Self;
+}
+
+impl Selection for AllPeers {
+    fn for_node(_cluster: &Cluster) -> Self {
+        AllPeers
+    }
+}
+
+impl Selection for RandomPeers {
+    fn for_node(_cluster: &Cluster) -> Self {
+        RandomPeers { fanout: 1 }
+    }
+}
+
+impl Selection for Neighbors {
+    /// A ring.
+    fn for_node(cluster: &Cluster) -> Self {
+        let nodes = cluster.nodes();
+        let next = nodes[(cluster.index() + 1) % nodes.len()];
+        Neighbors(vec![next])
+    }
+}
+
+impl<S: Selection> Node<(), Payload, Round> for SetNode<S> {
+    fn from_init(
+        _state: (),
+        init: Init,
+        _tx: Sender<Event<Payload, Round>>,
+    ) -> anyhow::Result<Self> {
+        let cluster = Cluster::new(&init)?;
+        let selection = S::for_node(&cluster);
+        Ok(Self {
+            gossip: Gossip::new(&cluster, GSet::new(), selection, Payload::Gossip)
+                .with_interval(Duration::from_millis(100)),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, Round>,
+        outbox: &mut Outbox<Round>,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::Injected(Round) => self.gossip.round(outbox)?,
+            Event::Message(msg) => match msg.body.payload {
+                Payload::Add { value } => {
+                    self.gossip.state_mut().insert(value);
+                }
+                Payload::Gossip(sync) => self.gossip.receive(msg.src, &sync),
+            },
+            Event::EOF => {}
+        }
+        Ok(())
+    }
+
+    fn periodic(&self) -> Vec<(Duration, Round)> {
+        vec![(self.gossip.interval(), Round)]
+    }
+}
+
+fn converges<S: Selection>(faults: Faults) -> anyhow::Result<()> {
+    let mut sim = Simulation::<(), SetNode<S>, Payload, Round>::new(7, 5, ())?.with_faults(faults);
+    let nodes = sim.node_ids().to_vec();
+    for value in 0..50 {
+        sim.send(&Message {
+            src: NodeId::client(1),
+            dst: nodes[value as usize % nodes.len()],
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                payload: Payload::Add { value },
+            },
+        })?;
+        sim.run_for(Duration::from_millis(20))?;
+    }
+    sim.run_for(Duration::from_secs(10))?;
+
+    let expected: GSet<u32> = (0..50).collect();
+    for &id in &nodes {
+        let node = sim.node(id).expect("node exists");
+        assert_eq!(node.gossip.state(), &expected, "{} has not caught up", id);
+    }
+    Ok(())
+}
+
+fn lossy() -> Faults {
+    Faults {
+        drop: 0.3,
+        duplicate: 0.1,
+        ..Faults::default()
+    }
+}
+
+#[test]
+fn all_peers_converge() -> anyhow::Result<()> {
+    converges::<AllPeers>(Faults::default())
+}
+
+#[test]
+fn all_peers_converge_despite_loss() -> anyhow::Result<()> {
+    converges::<AllPeers>(lossy())
+}
+
+#[test]
+fn random_peers_converge_despite_loss() -> anyhow::Result<()> {
+    converges::<RandomPeers>(lossy())
+}
+
+#[test]
+fn neighbors_converge_despite_loss() -> anyhow::Result<()> {
+    converges::<Neighbors>(lossy())
+}
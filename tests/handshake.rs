@@ -0,0 +1,164 @@
+use rustengan::middleware::Identity;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Echo { echo: String },
+    EchoOk { echo: String },
+}
+
+struct EchoNode;
+
+impl Node<(), Payload> for EchoNode {
+    fn from_init(_state: (), _init: Init, _tx: Sender<Event<Payload>>) -> anyhow::Result<Self> {
+        Ok(EchoNode)
+    }
+
+    fn step(&mut self, input: Event<Payload>, outbox: &mut Outbox) -> anyhow::Result<()> {
+        if let Event::Message(input) = input {
+            if let Payload::Echo { echo } = &input.body.payload {
+                let echo = echo.clone();
+                outbox.reply(&input, Payload::EchoOk { echo })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An [`EchoNode`] run by `main_loop_on` over a memory pipe.
+struct Session {
+    node: JoinHandle<anyhow::Result<()>>,
+    to_node: MemoryWriter,
+    from_node: MemoryReader,
+}
+
+impl Session {
+    fn start() -> Self {
+        let (node_end, client_end) = MemoryTransport::pair();
+        let node =
+            thread::spawn(move || main_loop_on::<_, EchoNode, _, _, _, _>(node_end, (), Identity));
+        let (from_node, to_node) = client_end.split().expect("memory transports split");
+        Self {
+            node,
+            to_node,
+            from_node,
+        }
+    }
+
+    fn send(&mut self, body: Value) {
+        let msg = json!({ "src": "c1", "dest": "n1", "body": body });
+        writeln!(self.to_node, "{}", msg).expect("node reads its input");
+    }
+
+    fn init(&mut self, node_id: &str) {
+        self.send(
+            json!({ "type": "init", "msg_id": 1, "node_id": node_id, "node_ids": ["n1", "n2"] }),
+        );
+    }
+
+    fn recv(&mut self) -> Value {
+        let mut line = String::new();
+        self.from_node.read_line(&mut line).expect("node writes");
+        let msg: Value = serde_json::from_str(&line).expect("node writes JSON");
+        msg["body"].clone()
+    }
+
+    /// Closes the node's input and returns what `main_loop_on` did.
+    fn finish(self) -> anyhow::Result<()> {
+        drop(self.to_node);
+        self.node.join().expect("node thread")
+    }
+}
+
+fn echo(id: usize) -> Value {
+    json!({ "type": "echo", "msg_id": id, "echo": id.to_string() })
+}
+
+#[test]
+fn an_init_for_another_node_is_refused() {
+    let mut session = Session::start();
+    session.init("n3");
+    let reply = session.recv();
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], 12);
+    assert_eq!(reply["in_reply_to"], 1);
+
+    let e = session.finish().expect_err("init fails");
+    assert!(
+        matches!(e.downcast_ref(), Some(InitError::UnknownNode { .. })),
+        "{:#}",
+        e
+    );
+}
+
+#[test]
+fn a_malformed_init_is_refused() {
+    let mut session = Session::start();
+    session.send(json!({ "type": "init", "msg_id": 1, "node_id": "n1" }));
+    let reply = session.recv();
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], 12);
+    assert_eq!(reply["in_reply_to"], 1);
+
+    let e = session.finish().expect_err("init fails");
+    assert!(
+        matches!(e.downcast_ref(), Some(InitError::Malformed(_))),
+        "{:#}",
+        e
+    );
+}
+
+#[test]
+fn requests_before_init_are_handled_after_it() -> anyhow::Result<()> {
+    let mut session = Session::start();
+    session.send(echo(2));
+    session.send(echo(3));
+    session.init("n1");
+    assert_eq!(session.recv()["type"], "init_ok");
+    for id in [2, 3] {
+        let reply = session.recv();
+        assert_eq!(reply["type"], "echo_ok");
+        assert_eq!(reply["in_reply_to"], id);
+    }
+    session.finish()
+}
+
+#[test]
+fn requests_beyond_the_early_limit_are_turned_away() -> anyhow::Result<()> {
+    let mut session = Session::start();
+    let first = 2;
+    let turned_away = first + EARLY_LIMIT;
+    for id in first..turned_away + 2 {
+        session.send(echo(id));
+    }
+    for id in turned_away..turned_away + 2 {
+        let reply = session.recv();
+        assert_eq!(reply["type"], "error");
+        assert_eq!(reply["code"], 11);
+        assert_eq!(reply["in_reply_to"], id);
+    }
+
+    session.init("n1");
+    assert_eq!(session.recv()["type"], "init_ok");
+    for id in first..turned_away {
+        let reply = session.recv();
+        assert_eq!(reply["type"], "echo_ok");
+        assert_eq!(reply["in_reply_to"], id);
+    }
+    session.finish()
+}
+
+#[test]
+fn eof_before_init_is_an_error() {
+    let mut session = Session::start();
+    session.send(echo(2));
+    let e = session.finish().expect_err("init never came");
+    assert!(matches!(e.downcast_ref(), Some(InitError::Eof)), "{:#}", e);
+}
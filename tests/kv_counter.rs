@@ -0,0 +1,94 @@
+use rustengan::kv_counter::{InjectedPayload, KvCounterNode, Payload};
+use rustengan::sim::Simulation;
+use rustengan::*;
+use serde_json::Value;
+use std::time::Duration;
+
+type Sim = Simulation<(), KvCounterNode, Payload, InjectedPayload>;
+
+fn request(sim: &mut Sim, id: usize, node: NodeId, payload: Payload) -> anyhow::Result<()> {
+    sim.send(&Message {
+        src: NodeId::client(1),
+        dst: node,
+        body: Body {
+            id: Some(id),
+            in_reply_to: None,
+            payload,
+        },
+    })
+}
+
+fn kinds(replies: &[Message<Value>]) -> Vec<&str> {
+    replies
+        .iter()
+        .map(|reply| reply.body.payload["type"].as_str().unwrap_or("untyped"))
+        .collect()
+}
+
+#[test]
+fn concurrent_adds_all_count() -> anyhow::Result<()> {
+    let mut sim = Sim::new(11, 4, ())?;
+    let nodes = sim.node_ids().to_vec();
+    // All at once, on three of the nodes, so that their swaps collide.
+    let mut total = 0;
+    for id in 0..30 {
+        let delta = id as u64 % 3 + 1;
+        total += delta;
+        request(&mut sim, id, nodes[id % 3], Payload::Add { delta })?;
+    }
+    sim.run_for(Duration::from_secs(10))?;
+    let replies = sim.take_client_messages();
+    assert_eq!(kinds(&replies), vec!["add_ok"; 30]);
+    assert!(sim
+        .logs()
+        .iter()
+        .any(|(_, _, line)| line.starts_with("lost a cas race")));
+
+    // The fourth node has not talked to seq-kv yet; without its barrier
+    // write it could read the counter as it was long ago.
+    for (i, &node) in nodes.iter().enumerate() {
+        request(&mut sim, 100 + i, node, Payload::Read)?;
+    }
+    sim.run_for(Duration::from_secs(5))?;
+    let replies = sim.take_client_messages();
+    assert_eq!(replies.len(), nodes.len());
+    for reply in replies {
+        assert_eq!(reply.body.payload["type"], "read_ok");
+        assert_eq!(
+            reply.body.payload["value"], total,
+            "read from {}",
+            reply.src
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn read_of_a_fresh_counter_is_zero() -> anyhow::Result<()> {
+    let mut sim = Sim::new(12, 1, ())?;
+    request(&mut sim, 1, NodeId::server(0), Payload::Read)?;
+    sim.run_for(Duration::from_secs(5))?;
+    let replies = sim.take_client_messages();
+    assert_eq!(kinds(&replies), ["read_ok"]);
+    assert_eq!(replies[0].body.payload["value"], 0);
+    Ok(())
+}
+
+#[test]
+fn gives_up_when_seq_kv_is_out_of_reach() -> anyhow::Result<()> {
+    // Every call to seq-kv times out before its reply is back.
+    let slow = Duration::from_millis(700);
+    let mut sim = Sim::new(13, 1, ())?.with_latency(slow, slow);
+    let node = NodeId::server(0);
+    request(&mut sim, 1, node, Payload::Read)?;
+    request(&mut sim, 2, node, Payload::Add { delta: 2 })?;
+    sim.run_for(Duration::from_secs(30))?;
+
+    let replies = sim.take_client_messages();
+    assert_eq!(replies.len(), 2);
+    for reply in replies {
+        let ErrorPayload::Error(error) = serde_json::from_value(reply.body.payload)?;
+        assert_eq!(error.code, ErrorCode::TemporarilyUnavailable);
+    }
+    Ok(())
+}
@@ -0,0 +1,249 @@
+use rustengan::middleware::{CatchPanicLayer, DedupLayer, Layer, Layers, RateLimitLayer, Service};
+use rustengan::*;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn request(src: NodeId, id: usize) -> Event<Value> {
+    Event::Message(Message {
+        src,
+        dst: NodeId::server(0),
+        body: Body {
+            id: Some(id),
+            in_reply_to: None,
+            payload: json!({"type": "read"}),
+        },
+    })
+}
+
+fn sent(outbox: &mut Outbox) -> Vec<Message<Value>> {
+    outbox
+        .take_effects()
+        .into_iter()
+        .filter_map(|effect| match effect {
+            Effect::Send(msg) => Some(msg),
+            _ => None,
+        })
+        .collect()
+}
+
+fn error_code(e: &anyhow::Error) -> ErrorCode {
+    e.downcast_ref::<ErrorBody>().expect("an ErrorBody").code
+}
+
+#[test]
+fn dedup_replays_the_reply_to_a_repeated_request() -> anyhow::Result<()> {
+    let mut calls = 0;
+    let mut service = DedupLayer::default().layer(|input: Event<Value>, outbox: &mut Outbox| {
+        calls += 1;
+        if let Event::Message(msg) = input {
+            outbox.reply(&msg, json!({"type": "read_ok", "value": calls}))?;
+        }
+        Ok(())
+    });
+    let mut outbox = Outbox::new();
+    let client = NodeId::client(1);
+
+    service.call(request(client, 1), &mut outbox)?;
+    let first = sent(&mut outbox);
+    service.call(request(client, 1), &mut outbox)?;
+    let again = sent(&mut outbox);
+    assert_eq!(first.len(), 1);
+    assert_eq!(again.len(), 1);
+    assert_eq!(again[0].body.payload, first[0].body.payload);
+    assert_eq!(again[0].body.in_reply_to, Some(1));
+
+    // Another msg_id is another request.
+    service.call(request(client, 2), &mut outbox)?;
+    assert_eq!(sent(&mut outbox)[0].body.payload["value"], 2);
+    Ok(())
+}
+
+#[test]
+fn dedup_replays_a_reply_sent_later() -> anyhow::Result<()> {
+    // Answers requests only once a timer (or rpc continuation) fires.
+    let mut pending = Vec::new();
+    let mut calls = 0;
+    let mut service = DedupLayer::default().layer(|input: Event<Value>, outbox: &mut Outbox| {
+        calls += 1;
+        match input {
+            Event::Message(msg) => pending.push(msg),
+            Event::Injected(()) => {
+                for msg in pending.drain(..) {
+                    outbox.reply(&msg, json!({"type": "read_ok"}))?;
+                }
+            }
+            Event::EOF => {}
+        }
+        Ok(())
+    });
+    let mut outbox = Outbox::new();
+    let client = NodeId::client(1);
+
+    service.call(request(client, 1), &mut outbox)?;
+    assert!(sent(&mut outbox).is_empty());
+    // Still outstanding: neither handled again nor answered.
+    service.call(request(client, 1), &mut outbox)?;
+    assert!(sent(&mut outbox).is_empty());
+
+    service.call(Event::Injected(()), &mut outbox)?;
+    assert_eq!(sent(&mut outbox).len(), 1);
+
+    service.call(request(client, 1), &mut outbox)?;
+    let replayed = sent(&mut outbox);
+    assert_eq!(replayed.len(), 1);
+    assert_eq!(replayed[0].body.payload["type"], "read_ok");
+    assert_eq!(replayed[0].body.in_reply_to, Some(1));
+    drop(service);
+    assert_eq!(calls, 2);
+    Ok(())
+}
+
+#[test]
+fn dedup_forgets_the_oldest_request_beyond_capacity() -> anyhow::Result<()> {
+    let mut calls = 0;
+    let mut service = DedupLayer::new(1).layer(|_: Event<Value>, _: &mut Outbox| {
+        calls += 1;
+        Ok(())
+    });
+    let mut outbox = Outbox::new();
+    let client = NodeId::client(1);
+    service.call(request(client, 1), &mut outbox)?;
+    service.call(request(client, 2), &mut outbox)?;
+    service.call(request(client, 1), &mut outbox)?;
+    drop(service);
+    assert_eq!(calls, 3);
+    Ok(())
+}
+
+#[test]
+fn catch_panic_answers_requests_with_crash() {
+    let mut service = CatchPanicLayer.layer(|input: Event<Value>, _: &mut Outbox| {
+        if let Event::Message(_) = input {
+            panic!("boom");
+        }
+        Ok(())
+    });
+    let mut outbox = Outbox::new();
+    let e = service
+        .call(request(NodeId::client(1), 1), &mut outbox)
+        .expect_err("the panic becomes an error");
+    assert_eq!(error_code(&e), ErrorCode::Crash);
+    assert!(e.to_string().contains("boom"), "{}", e);
+}
+
+#[test]
+fn catch_panic_logs_panics_outside_requests() -> anyhow::Result<()> {
+    let mut service =
+        CatchPanicLayer.layer(|_: Event<Value>, _: &mut Outbox| -> anyhow::Result<()> {
+            panic!("tick went wrong");
+        });
+    let mut outbox = Outbox::new();
+    service.call(Event::Injected(()), &mut outbox)?;
+    let logged = outbox.take_effects().into_iter().any(
+        |effect| matches!(effect, Effect::Log(line) if line == "node panicked: tick went wrong"),
+    );
+    assert!(logged);
+    Ok(())
+}
+
+#[test]
+fn rate_limit_turns_away_client_requests_beyond_the_burst() -> anyhow::Result<()> {
+    let mut calls = 0;
+    // No refill to speak of, so only the burst gets through.
+    let mut service = RateLimitLayer::new(0.0, 2).layer(|_: Event<Value>, _: &mut Outbox| {
+        calls += 1;
+        Ok(())
+    });
+    let mut outbox = Outbox::new();
+    let client = NodeId::client(1);
+    service.call(request(client, 1), &mut outbox)?;
+    service.call(request(client, 2), &mut outbox)?;
+    let e = service
+        .call(request(client, 3), &mut outbox)
+        .expect_err("over the limit");
+    assert_eq!(error_code(&e), ErrorCode::TemporarilyUnavailable);
+
+    // Servers and timers are never limited.
+    service.call(request(NodeId::server(1), 1), &mut outbox)?;
+    service.call(Event::Injected(()), &mut outbox)?;
+    drop(service);
+    assert_eq!(calls, 4);
+    Ok(())
+}
+
+/// Notes its name in `seen` before passing events on.
+struct Tag {
+    name: &'static str,
+    seen: Rc<RefCell<Vec<&'static str>>>,
+}
+
+impl<S: Service<Value>> Layer<S> for Tag {
+    type Service = Tagged<S>;
+
+    fn layer(&self, inner: S) -> Tagged<S> {
+        Tagged {
+            name: self.name,
+            seen: Rc::clone(&self.seen),
+            inner,
+        }
+    }
+}
+
+struct Tagged<S> {
+    name: &'static str,
+    seen: Rc<RefCell<Vec<&'static str>>>,
+    inner: S,
+}
+
+impl<S: Service<Value>> Service<Value> for Tagged<S> {
+    fn call(&mut self, input: Event<Value>, outbox: &mut Outbox) -> anyhow::Result<()> {
+        self.seen.borrow_mut().push(self.name);
+        self.inner.call(input, outbox)
+    }
+}
+
+#[test]
+fn first_layer_added_sees_events_first() -> anyhow::Result<()> {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let tag = |name| Tag {
+        name,
+        seen: Rc::clone(&seen),
+    };
+    let node_seen = Rc::clone(&seen);
+    let layers = Layers::new()
+        .layer(tag("outer"))
+        .layer(tag("middle"))
+        .layer(tag("inner"));
+    let mut service = Layer::layer(&layers, move |_: Event<Value>, _: &mut Outbox| {
+        node_seen.borrow_mut().push("node");
+        Ok(())
+    });
+    service.call(Event::Injected(()), &mut Outbox::new())?;
+    assert_eq!(*seen.borrow(), ["outer", "middle", "inner", "node"]);
+    Ok(())
+}
+
+#[test]
+fn dedup_outside_rate_limit_replays_without_spending_tokens() -> anyhow::Result<()> {
+    let layers = Layers::new()
+        .layer(DedupLayer::default())
+        .layer(RateLimitLayer::new(0.0, 1));
+    let mut service = Layer::layer(&layers, |input: Event<Value>, outbox: &mut Outbox| {
+        if let Event::Message(msg) = input {
+            outbox.reply(&msg, json!({"type": "read_ok"}))?;
+        }
+        Ok(())
+    });
+    let mut outbox = Outbox::new();
+    let client = NodeId::client(1);
+    service.call(request(client, 1), &mut outbox)?;
+    // The one token is spent, but a retry is answered from the cache.
+    service.call(request(client, 1), &mut outbox)?;
+    assert_eq!(sent(&mut outbox).len(), 2);
+    let e = service
+        .call(request(client, 2), &mut outbox)
+        .expect_err("over the limit");
+    assert_eq!(error_code(&e), ErrorCode::TemporarilyUnavailable);
+    Ok(())
+}
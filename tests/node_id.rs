@@ -0,0 +1,97 @@
+use rustengan::crdt::GCounter;
+use rustengan::NodeId;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+
+#[test]
+fn numbered_names_are_servers_and_clients() {
+    assert_eq!(NodeId::from("n0"), NodeId::server(0));
+    assert_eq!(NodeId::from("n12"), NodeId::server(12));
+    assert_eq!(NodeId::from("c3"), NodeId::client(3));
+    assert!(NodeId::from("n7").is_server());
+    assert!(NodeId::from("c7").is_client());
+    assert_eq!(NodeId::from("n7").server_index(), Some(7));
+    assert_eq!(NodeId::server(7), "n7");
+}
+
+#[test]
+fn other_names_stay_as_they_are() {
+    // Numbers are only taken as written canonically, and as they fit.
+    for name in [
+        "n01",
+        "c00",
+        "n",
+        "c",
+        "nx",
+        "n-1",
+        "n+1",
+        "n4294967296",
+        "seq-kv",
+    ] {
+        let id = NodeId::from(name);
+        assert!(!id.is_server() && !id.is_client(), "{}", name);
+        assert_eq!(id.server_index(), None);
+        assert_eq!(id.to_string(), name);
+        assert_eq!(id, name);
+    }
+    assert_ne!(NodeId::from("n01"), NodeId::server(1));
+    // Interned names compare by their text.
+    assert_eq!(NodeId::from("lin-kv"), NodeId::from(String::from("lin-kv")));
+}
+
+#[test]
+fn servers_sort_by_number_before_clients_and_names() {
+    let mut ids: Vec<NodeId> = ["lin-kv", "c2", "n10", "c10", "n2", "a", "n01", "n0"]
+        .into_iter()
+        .map(NodeId::from)
+        .collect();
+    ids.sort();
+    let names: Vec<String> = ids.iter().map(NodeId::to_string).collect();
+    assert_eq!(
+        names,
+        ["n0", "n2", "n10", "c2", "c10", "a", "lin-kv", "n01"]
+    );
+
+    assert!(NodeId::server(u32::MAX) < NodeId::client(0));
+    assert!(NodeId::client(u32::MAX) < NodeId::from(""));
+}
+
+#[test]
+fn n0_is_the_least_id() {
+    // `ORSet` looks up a value's inserts starting from `n0`.
+    for name in ["n1", "c0", "", "a", "n00", "seq-kv"] {
+        assert!(NodeId::server(0) < NodeId::from(name), "{}", name);
+    }
+}
+
+#[test]
+fn serializes_as_the_plain_name() -> serde_json::Result<()> {
+    for name in ["n3", "c14", "n01", "seq-kv"] {
+        let id = NodeId::from(name);
+        assert_eq!(serde_json::to_value(id)?, json!(name));
+        assert_eq!(serde_json::from_value::<NodeId>(json!(name))?, id);
+    }
+    Ok(())
+}
+
+#[test]
+fn round_trips_as_map_keys() -> serde_json::Result<()> {
+    let topology = json!({ "n0": ["n1", "n10"], "n1": ["n0"], "n10": ["n0"] });
+    let parsed: HashMap<NodeId, Vec<NodeId>> = serde_json::from_value(topology.clone())?;
+    assert_eq!(
+        parsed[&NodeId::server(0)],
+        [NodeId::server(1), NodeId::server(10)]
+    );
+    assert_eq!(serde_json::to_value(&parsed)?, topology);
+
+    let ordered: BTreeMap<NodeId, Vec<NodeId>> = serde_json::from_value(topology.clone())?;
+    assert_eq!(serde_json::to_value(&ordered)?, topology);
+
+    let mut counter = GCounter::new();
+    counter.increment(NodeId::server(2), 3);
+    counter.increment(NodeId::server(10), 4);
+    let wire = serde_json::to_value(&counter)?;
+    assert_eq!(wire, json!({ "n2": 3, "n10": 4 }));
+    assert_eq!(serde_json::from_value::<GCounter>(wire)?, counter);
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use rustengan::{BatchWriter, OutputStats};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Keeps every write it gets as a batch of its own.
+#[derive(Clone, Default)]
+struct Batches(Rc<RefCell<Vec<Vec<u8>>>>);
+
+impl Batches {
+    fn take(&self) -> Vec<String> {
+        self.0
+            .borrow_mut()
+            .drain(..)
+            .map(|batch| String::from_utf8(batch).expect("utf-8"))
+            .collect()
+    }
+}
+
+impl Write for Batches {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().push(data.to_vec());
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn holds_lines_back_until_flush() -> io::Result<()> {
+    let batches = Batches::default();
+    let mut writer = BatchWriter::with_limit(batches.clone(), 1024);
+    writer.write_all(b"one\n")?;
+    writer.write_all(b"two\n")?;
+    assert!(batches.take().is_empty());
+
+    writer.flush()?;
+    assert_eq!(batches.take(), ["one\ntwo\n"]);
+    Ok(())
+}
+
+#[test]
+fn batches_at_the_limit_end_on_a_line_boundary() -> io::Result<()> {
+    let batches = Batches::default();
+    let mut writer = BatchWriter::with_limit(batches.clone(), 10);
+    writer.write_all(b"aaaa\nbbbb\n")?;
+    assert_eq!(batches.take(), ["aaaa\nbbbb\n"]);
+
+    // The limit is reached halfway through a line: only the whole lines go.
+    writer.write_all(b"cccc\nddd")?;
+    assert!(batches.take().is_empty());
+    writer.write_all(b"ddd")?;
+    assert_eq!(batches.take(), ["cccc\n"]);
+    writer.write_all(b"\neeee")?;
+    assert_eq!(batches.take(), ["dddddd\n"]);
+
+    writer.write_all(b"\n")?;
+    assert!(batches.take().is_empty());
+    writer.flush()?;
+    assert_eq!(batches.take(), ["eeee\n"]);
+    assert_eq!(
+        writer.stats(),
+        OutputStats {
+            lines: 5,
+            bytes: 27,
+            writes: 4,
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn a_line_longer_than_the_limit_waits_for_its_end() -> io::Result<()> {
+    let batches = Batches::default();
+    let mut writer = BatchWriter::with_limit(batches.clone(), 4);
+    writer.write_all(b"abcdefgh")?;
+    assert!(batches.take().is_empty());
+    writer.write_all(b"ij\nk")?;
+    assert_eq!(batches.take(), ["abcdefghij\n"]);
+    drop(writer);
+    // Dropping flushes what is left, even without a newline.
+    assert_eq!(batches.take(), ["k"]);
+    Ok(())
+}
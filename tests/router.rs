@@ -0,0 +1,188 @@
+use rustengan::sim::Simulation;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize)]
+struct Add {
+    delta: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct ReadOk {
+    value: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Read {}
+
+struct SumNode {
+    total: u64,
+}
+
+impl SumNode {
+    fn add(&mut self, Add { delta }: Add) -> anyhow::Result<()> {
+        self.total += delta;
+        Ok(())
+    }
+
+    fn read(&mut self, _: Read) -> anyhow::Result<ReadOk> {
+        Ok(ReadOk { value: self.total })
+    }
+}
+
+impl Routes<()> for SumNode {
+    fn from_init(_state: (), _init: Init, _tx: Sender<Event<RawPayload>>) -> anyhow::Result<Self> {
+        Ok(SumNode { total: 0 })
+    }
+
+    fn router() -> Router<Self> {
+        Router::new()
+            .handle("add", Self::add)
+            .handle("read", Self::read)
+    }
+}
+
+/// Answers whatever it has no handler for with an `unknown` reply.
+struct CatchAllNode;
+
+impl Routes<()> for CatchAllNode {
+    fn from_init(_state: (), _init: Init, _tx: Sender<Event<RawPayload>>) -> anyhow::Result<Self> {
+        Ok(CatchAllNode)
+    }
+
+    fn router() -> Router<Self> {
+        Router::new().fallback(|_, msg, outbox| {
+            let kind = msg.body.payload.kind().to_string();
+            outbox.reply(&msg, json!({ "type": "unknown", "was": kind }))?;
+            Ok(())
+        })
+    }
+}
+
+fn message(id: Option<usize>, in_reply_to: Option<usize>, body: Value) -> Message<Value> {
+    Message {
+        src: NodeId::client(1),
+        dst: NodeId::server(0),
+        body: Body {
+            id,
+            in_reply_to,
+            payload: body,
+        },
+    }
+}
+
+fn replies<N>(sim: &mut Simulation<(), Routed<N>, RawPayload, ()>) -> Vec<Message<Value>>
+where
+    N: Routes<()>,
+{
+    sim.run_for(Duration::from_secs(1)).expect("runs");
+    sim.take_client_messages()
+}
+
+#[test]
+fn handlers_reply_with_their_kind_ok() -> anyhow::Result<()> {
+    let mut sim = Simulation::<(), Routed<SumNode>, RawPayload, ()>::new(1, 1, ())?;
+    let requests = [
+        json!({ "type": "add", "delta": 3 }),
+        json!({ "type": "add", "delta": 4 }),
+        json!({ "type": "read" }),
+    ];
+    let mut bodies = Vec::new();
+    // One at a time, so that the read comes after both adds.
+    for (id, request) in requests.into_iter().enumerate() {
+        sim.send(&message(Some(id + 1), None, request))?;
+        for reply in replies(&mut sim) {
+            bodies.push((reply.body.in_reply_to, reply.body.payload));
+        }
+    }
+    assert_eq!(
+        bodies,
+        [
+            // A `()` response makes for a reply with nothing but its type.
+            (Some(1), json!({ "type": "add_ok" })),
+            (Some(2), json!({ "type": "add_ok" })),
+            (Some(3), json!({ "type": "read_ok", "value": 7 })),
+        ]
+    );
+    let node = sim.node(NodeId::server(0)).expect("a node");
+    assert_eq!(node.node().total, 7);
+    Ok(())
+}
+
+#[test]
+fn bad_bodies_are_malformed_requests() -> anyhow::Result<()> {
+    let mut sim = Simulation::<(), Routed<SumNode>, RawPayload, ()>::new(1, 1, ())?;
+    sim.send(&message(
+        Some(1),
+        None,
+        json!({ "type": "add", "delta": "x" }),
+    ))?;
+
+    let replies = replies(&mut sim);
+    assert_eq!(replies.len(), 1);
+    let ErrorPayload::Error(error) = serde_json::from_value(replies[0].body.payload.clone())?;
+    assert_eq!(error.code, ErrorCode::MalformedRequest);
+    Ok(())
+}
+
+#[test]
+fn unhandled_requests_are_not_supported() -> anyhow::Result<()> {
+    let mut sim = Simulation::<(), Routed<SumNode>, RawPayload, ()>::new(1, 1, ())?;
+    sim.send(&message(
+        Some(1),
+        None,
+        json!({ "type": "echo", "echo": "hi" }),
+    ))?;
+
+    let replies = replies(&mut sim);
+    assert_eq!(replies.len(), 1);
+    let ErrorPayload::Error(error) = serde_json::from_value(replies[0].body.payload.clone())?;
+    assert_eq!(error.code, ErrorCode::NotSupported);
+    Ok(())
+}
+
+#[test]
+fn the_fallback_gets_what_nothing_handles() -> anyhow::Result<()> {
+    let mut sim = Simulation::<(), Routed<CatchAllNode>, RawPayload, ()>::new(1, 1, ())?;
+    sim.send(&message(
+        Some(1),
+        None,
+        json!({ "type": "echo", "echo": "hi" }),
+    ))?;
+
+    let replies = replies(&mut sim);
+    assert_eq!(replies.len(), 1);
+    assert_eq!(
+        replies[0].body.payload,
+        json!({ "type": "unknown", "was": "echo" })
+    );
+    Ok(())
+}
+
+#[test]
+fn stray_replies_are_ignored() -> anyhow::Result<()> {
+    let mut sim = Simulation::<(), Routed<SumNode>, RawPayload, ()>::new(1, 1, ())?;
+    // A reply of a type nothing handles, and one that does not parse as
+    // what its type's handler takes.
+    sim.send(&message(None, Some(5), json!({ "type": "write_ok" })))?;
+    sim.send(&message(
+        None,
+        Some(6),
+        json!({ "type": "add", "delta": "x" }),
+    ))?;
+
+    assert!(replies(&mut sim).is_empty());
+    let logs: Vec<_> = sim
+        .logs()
+        .iter()
+        .map(|(_, _, line)| line.as_str())
+        .collect();
+    assert!(logs
+        .iter()
+        .any(|line| line.starts_with("ignoring write_ok")));
+    assert!(logs.iter().any(|line| line.starts_with("dropping add")));
+    Ok(())
+}
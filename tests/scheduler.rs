@@ -0,0 +1,211 @@
+use rustengan::sim::Simulation;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+fn scheduler() -> (ManualClock, Scheduler<&'static str, ManualClock>) {
+    let clock = ManualClock::new();
+    (clock.clone(), Scheduler::new(clock, 3))
+}
+
+#[test]
+fn missed_ticks_fire_once() {
+    let (clock, mut timers) = scheduler();
+    timers.insert(TimerId(1), ms(100), Some(ms(100)), ms(0), "tick");
+
+    clock.set(ms(1050));
+    assert_eq!(timers.expired(), ["tick"]);
+    // A full period from now, not from the ticks that were missed.
+    assert_eq!(timers.next_deadline(), Some(ms(1150)));
+    clock.set(ms(1149));
+    assert!(timers.expired().is_empty());
+    clock.set(ms(1150));
+    assert_eq!(timers.expired(), ["tick"]);
+}
+
+#[test]
+fn rearmed_timers_fire_at_their_new_deadline() {
+    let (clock, mut timers) = scheduler();
+    timers.insert(TimerId(1), ms(100), None, ms(0), "old");
+    timers.insert(TimerId(1), ms(300), None, ms(0), "new");
+    assert_eq!(timers.next_deadline(), Some(ms(300)));
+
+    clock.set(ms(100));
+    assert!(timers.expired().is_empty());
+    clock.set(ms(300));
+    assert_eq!(timers.expired(), ["new"]);
+    assert!(timers.is_empty());
+}
+
+#[test]
+fn cancelled_timers_do_not_fire() {
+    let (clock, mut timers) = scheduler();
+    timers.insert(TimerId(1), ms(100), Some(ms(100)), ms(0), "cancelled");
+    timers.insert(TimerId(2), ms(200), None, ms(0), "kept");
+    assert!(timers.cancel(TimerId(1)));
+    assert!(!timers.cancel(TimerId(1)));
+    assert_eq!(timers.next_deadline(), Some(ms(200)));
+
+    clock.set(ms(1000));
+    assert_eq!(timers.expired(), ["kept"]);
+    assert!(timers.is_empty());
+    assert_eq!(timers.next_deadline(), None);
+}
+
+#[test]
+fn a_timer_rearmed_for_the_same_deadline_fires_once() {
+    let (clock, mut timers) = scheduler();
+    timers.insert(TimerId(1), ms(100), None, ms(0), "first");
+    timers.cancel(TimerId(1));
+    // Its stale heap entry has the same deadline as the live one.
+    timers.insert(TimerId(1), ms(100), None, ms(0), "second");
+
+    clock.set(ms(100));
+    assert_eq!(timers.expired(), ["second"]);
+    assert!(timers.expired().is_empty());
+}
+
+#[test]
+fn jitter_stays_within_its_bound() {
+    let (clock, mut timers) = scheduler();
+    timers.insert(TimerId(1), ms(100), Some(ms(100)), ms(10), "tick");
+    let mut last = Duration::ZERO;
+    let mut gaps = Vec::new();
+    for now in 1..=5000 {
+        clock.set(ms(now));
+        if !timers.expired().is_empty() {
+            gaps.push(ms(now) - last);
+            last = ms(now);
+        }
+    }
+    assert!(gaps.len() > 40);
+    assert!(
+        gaps.iter().all(|gap| (ms(100)..=ms(110)).contains(gap)),
+        "{:?}",
+        gaps
+    );
+    // Not all the same, or there was no jitter to speak of.
+    assert!(gaps.iter().any(|gap| *gap != gaps[0]));
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Start,
+}
+
+/// How a call ended, as its continuation saw it.
+#[derive(Debug, Clone)]
+struct Done(&'static str);
+
+/// Reads from `lin-kv` once asked to, and logs how the call went.
+struct CallerNode {
+    node: NodeId,
+    rpc: Rpc<Payload, Done>,
+}
+
+impl Node<(), Payload, Done> for CallerNode {
+    fn from_init(_state: (), init: Init, tx: Sender<Event<Payload, Done>>) -> anyhow::Result<Self> {
+        Ok(CallerNode {
+            node: init.node_id,
+            rpc: Rpc::new(tx),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, Done>,
+        outbox: &mut Outbox<Done>,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::Message(_) => {
+                let read = Message {
+                    src: self.node,
+                    dst: NodeId::from("lin-kv"),
+                    body: Body {
+                        id: None,
+                        in_reply_to: None,
+                        payload: json!({ "type": "read", "key": "k" }),
+                    },
+                };
+                self.rpc.call(
+                    &read,
+                    ms(500),
+                    outbox,
+                    |reply: Result<Message<Value>, _>| match reply {
+                        Err(RpcError::Timeout) => Done("timed out"),
+                        _ => Done("answered"),
+                    },
+                )
+            }
+            Event::Injected(Done(how)) => {
+                outbox.log(format!("call {}", how));
+                Ok(())
+            }
+            Event::EOF => Ok(()),
+        }
+    }
+
+    fn rpc(&self) -> Option<Rpc<Payload, Done>> {
+        Some(self.rpc.clone())
+    }
+}
+
+/// Has the one node in `sim` call `lin-kv`, and returns how its calls ended.
+fn calls(mut sim: Simulation<(), CallerNode, Payload, Done>) -> anyhow::Result<Vec<String>> {
+    let node = NodeId::server(0);
+    sim.send(&Message {
+        src: NodeId::client(1),
+        dst: node,
+        body: Body {
+            id: Some(1),
+            in_reply_to: None,
+            payload: Payload::Start,
+        },
+    })?;
+    // Well past the call's timeout, and past the reply of a slow `lin-kv`.
+    sim.run_for(Duration::from_secs(3))?;
+    let caller = sim.node(node).expect("a node");
+    assert_eq!(caller.rpc.outstanding(), 0);
+    Ok(sim
+        .logs()
+        .iter()
+        .map(|(_, _, line)| line.clone())
+        .filter(|line| line.starts_with("call"))
+        .collect())
+}
+
+#[test]
+fn the_timeout_of_an_answered_call_does_nothing() -> anyhow::Result<()> {
+    let sim = Simulation::new(4, 1, ())?;
+    assert_eq!(calls(sim)?, ["call answered"]);
+    Ok(())
+}
+
+#[test]
+fn a_reply_after_the_timeout_does_nothing() -> anyhow::Result<()> {
+    let slow = ms(400);
+    let sim = Simulation::new(4, 1, ())?.with_latency(slow, slow);
+    assert_eq!(calls(sim)?, ["call timed out"]);
+    Ok(())
+}
+
+#[test]
+fn a_zero_period_is_raised_to_the_minimum() {
+    let (clock, mut timers) = scheduler();
+    timers.insert(TimerId(1), ms(0), Some(ms(0)), ms(0), "tick");
+    assert_eq!(timers.expired(), ["tick"]);
+    assert_eq!(timers.next_deadline(), Some(MIN_PERIOD));
+    assert!(timers.expired().is_empty());
+
+    clock.set(ms(10));
+    assert_eq!(timers.expired(), ["tick"]);
+    assert_eq!(timers.next_deadline(), Some(ms(10) + MIN_PERIOD));
+}
@@ -0,0 +1,213 @@
+use rustengan::kv::KvPayload;
+use rustengan::services::{Services, TsoPayload, SEQ_KV_HISTORY};
+use rustengan::*;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Sends `payload` from `client` to `service` and returns the reply, or the
+/// code of the error it was answered with.
+fn call<P: Serialize>(
+    services: &mut Services,
+    client: NodeId,
+    service: &str,
+    payload: P,
+) -> Result<Value, ErrorCode> {
+    let request = Message {
+        src: client,
+        dst: service.into(),
+        body: Body {
+            id: Some(1),
+            in_reply_to: None,
+            payload: serde_json::to_value(payload).expect("serializes"),
+        },
+    };
+    let reply = services.handle(request).expect("a known service");
+    assert_eq!(reply.dst, client);
+    assert_eq!(reply.body.in_reply_to, Some(1));
+    if reply.body.payload["type"] == "error" {
+        let ErrorPayload::Error(error) = serde_json::from_value(reply.body.payload).unwrap();
+        return Err(error.code);
+    }
+    Ok(reply.body.payload)
+}
+
+fn read(services: &mut Services, client: NodeId, service: &str) -> Result<Value, ErrorCode> {
+    let reply = call(
+        services,
+        client,
+        service,
+        KvPayload::<Value, Value>::Read { key: json!("k") },
+    )?;
+    Ok(reply["value"].clone())
+}
+
+fn write(services: &mut Services, client: NodeId, service: &str, value: u64) {
+    let op = KvPayload::Write {
+        key: json!("k"),
+        value: json!(value),
+    };
+    call(services, client, service, op).expect("writes succeed");
+}
+
+fn cas(
+    services: &mut Services,
+    service: &str,
+    from: u64,
+    to: u64,
+    create_if_not_exists: bool,
+) -> Result<Value, ErrorCode> {
+    let op = KvPayload::Cas {
+        key: json!("k"),
+        from: json!(from),
+        to: json!(to),
+        create_if_not_exists,
+    };
+    call(services, NodeId::server(0), service, op)
+}
+
+#[test]
+fn seq_kv_reads_your_writes() {
+    let mut services = Services::new(1);
+    let (n0, n1) = (NodeId::server(0), NodeId::server(1));
+    for value in 0..50 {
+        write(&mut services, n0, "seq-kv", value);
+        assert_eq!(read(&mut services, n0, "seq-kv"), Ok(json!(value)));
+        // Also when other clients are reading older states in between.
+        let _ = read(&mut services, n1, "seq-kv");
+    }
+}
+
+#[test]
+fn seq_kv_reads_are_monotonic_per_client() {
+    let mut services = Services::new(2);
+    let (writer, reader) = (NodeId::server(0), NodeId::server(1));
+    let mut last = None;
+    let mut stale = false;
+    for value in 0..100 {
+        write(&mut services, writer, "seq-kv", value);
+        let Ok(seen) = read(&mut services, reader, "seq-kv") else {
+            assert_eq!(last, None, "the key went missing again");
+            continue;
+        };
+        let seen = seen.as_u64().expect("a number");
+        if let Some(last) = last {
+            assert!(seen >= last, "read {} after {}", seen, last);
+        }
+        stale |= seen < value;
+        last = Some(seen);
+    }
+    // Otherwise this would not be testing much.
+    assert!(stale, "every read saw the latest write");
+}
+
+#[test]
+fn seq_kv_reads_no_older_than_its_history() {
+    let mut services = Services::new(3);
+    let (writer, reader) = (NodeId::server(0), NodeId::server(1));
+    // The reader observes the very first state, then falls far behind.
+    assert_eq!(
+        read(&mut services, reader, "seq-kv"),
+        Err(ErrorCode::KeyDoesNotExist)
+    );
+    let writes = 10 * SEQ_KV_HISTORY as u64;
+    for value in 0..writes {
+        write(&mut services, writer, "seq-kv", value);
+    }
+    let floor = writes - SEQ_KV_HISTORY as u64;
+    for client in [reader, NodeId::server(2)] {
+        for _ in 0..20 {
+            let seen = read(&mut services, client, "seq-kv").expect("the key exists by now");
+            assert!(seen.as_u64().unwrap() >= floor, "{} read {}", client, seen);
+        }
+    }
+}
+
+#[test]
+fn lin_kv_cas() {
+    let mut services = Services::new(4);
+    assert_eq!(
+        cas(&mut services, "lin-kv", 0, 1, false),
+        Err(ErrorCode::KeyDoesNotExist)
+    );
+    assert_eq!(
+        read(&mut services, NodeId::server(0), "lin-kv"),
+        Err(ErrorCode::KeyDoesNotExist)
+    );
+
+    let created = cas(&mut services, "lin-kv", 0, 1, true).expect("creates the key");
+    assert_eq!(created["type"], "cas_ok");
+    assert_eq!(
+        read(&mut services, NodeId::server(1), "lin-kv"),
+        Ok(json!(1))
+    );
+
+    assert_eq!(
+        cas(&mut services, "lin-kv", 0, 2, true),
+        Err(ErrorCode::PreconditionFailed)
+    );
+    assert_eq!(
+        cas(&mut services, "lin-kv", 1, 2, false).map(|reply| reply["type"].clone()),
+        Ok(json!("cas_ok"))
+    );
+    assert_eq!(
+        read(&mut services, NodeId::server(1), "lin-kv"),
+        Ok(json!(2))
+    );
+}
+
+#[test]
+fn lww_kv_converges_on_the_last_write() {
+    let mut services = Services::new(5);
+    for (i, value) in (1..=10).enumerate() {
+        write(&mut services, NodeId::server(i as u32 % 3), "lww-kv", value);
+    }
+    // Every operation also passes a replica's state on to another, so once
+    // writes stop the replicas agree.
+    let reads: Vec<_> = (0..200)
+        .map(|i| read(&mut services, NodeId::server(i % 3), "lww-kv"))
+        .collect();
+    assert!(
+        reads[150..].iter().all(|read| *read == Ok(json!(10))),
+        "{:?}",
+        &reads[150..]
+    );
+}
+
+#[test]
+fn lin_tso_hands_out_increasing_timestamps() {
+    let mut services = Services::new(6);
+    let mut last = None;
+    for i in 0..100 {
+        let reply = call(
+            &mut services,
+            NodeId::server(i % 3),
+            "lin-tso",
+            TsoPayload::Ts,
+        )
+        .expect("ts succeeds");
+        let ts = reply["ts"].as_u64().expect("a timestamp");
+        if let Some(last) = last {
+            assert!(ts > last, "{} after {}", ts, last);
+        }
+        last = Some(ts);
+    }
+}
+
+#[test]
+fn unknown_requests_are_not_supported() {
+    let mut services = Services::new(7);
+    let reply = call(
+        &mut services,
+        NodeId::server(0),
+        "lin-kv",
+        json!({"type": "scan"}),
+    );
+    assert_eq!(reply, Err(ErrorCode::NotSupported));
+    let reply = call(
+        &mut services,
+        NodeId::server(0),
+        "lin-kv",
+        json!({"type": "read"}),
+    );
+    assert_eq!(reply, Err(ErrorCode::MalformedRequest));
+}
@@ -0,0 +1,134 @@
+use rustengan::faults::Faults;
+use rustengan::sim::Simulation;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Add { value: u32 },
+    AddOk,
+    Note { value: u32 },
+}
+
+#[derive(Clone)]
+struct Tick;
+
+/// Tells its peers about every add and logs whatever happens to it, so that
+/// the logs record when each message and timer arrived.
+struct NoteNode {
+    cluster: Cluster,
+}
+
+impl Node<(), Payload, Tick> for NoteNode {
+    fn from_init(
+        _state: (),
+        init: Init,
+        _tx: Sender<Event<Payload, Tick>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            cluster: Cluster::new(&init)?,
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, Tick>,
+        outbox: &mut Outbox<Tick>,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::Message(msg) => match msg.body.payload {
+                Payload::Add { value } => {
+                    outbox.log(format!("add {} from {}", value, msg.src));
+                    for peer in self.cluster.peers() {
+                        outbox.send(&Message {
+                            src: self.cluster.me(),
+                            dst: peer,
+                            body: Body {
+                                id: None,
+                                in_reply_to: None,
+                                payload: Payload::Note { value },
+                            },
+                        })?;
+                    }
+                    outbox.reply(&msg, Payload::AddOk)?;
+                }
+                Payload::Note { value } => outbox.log(format!("note {} from {}", value, msg.src)),
+                Payload::AddOk => {}
+            },
+            Event::Injected(Tick) => outbox.log("tick"),
+            Event::EOF => {}
+        }
+        Ok(())
+    }
+
+    fn periodic(&self) -> Vec<(Duration, Tick)> {
+        vec![(Duration::from_millis(50), Tick)]
+    }
+}
+
+/// Everything a run can be told apart by.
+#[derive(Debug, PartialEq)]
+struct Run {
+    logs: Vec<(Duration, NodeId, String)>,
+    delivered: usize,
+    replies: Vec<String>,
+}
+
+fn run(seed: u64) -> anyhow::Result<Run> {
+    let faults = Faults {
+        drop: 0.2,
+        duplicate: 0.1,
+        ..Faults::default()
+    };
+    let mut sim = Simulation::<(), NoteNode, Payload, Tick>::new(seed, 4, ())?
+        .with_latency(Duration::from_millis(1), Duration::from_millis(20))
+        .with_faults(faults);
+    let nodes = sim.node_ids().to_vec();
+    let mut replies = Vec::new();
+    for value in 0..40 {
+        sim.send(&Message {
+            src: NodeId::client(1),
+            dst: nodes[value as usize % nodes.len()],
+            body: Body {
+                id: Some(value as usize),
+                in_reply_to: None,
+                payload: Payload::Add { value },
+            },
+        })?;
+        sim.run_for(Duration::from_millis(7))?;
+        replies.extend(sim.take_client_messages());
+    }
+    sim.run_for(Duration::from_secs(1))?;
+    replies.extend(sim.take_client_messages());
+    Ok(Run {
+        logs: sim.logs().to_vec(),
+        delivered: sim.delivered(),
+        replies: replies
+            .iter()
+            .map(serde_json::to_string::<Message<Value>>)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+#[test]
+fn same_seed_same_run() -> anyhow::Result<()> {
+    let first = run(42)?;
+    assert!(!first.replies.is_empty());
+    assert!(first.logs.iter().any(|(_, _, line)| line == "tick"));
+    assert_eq!(run(42)?, first);
+    Ok(())
+}
+
+#[test]
+fn other_seed_other_run() -> anyhow::Result<()> {
+    let first = run(42)?;
+    let other = run(43)?;
+    assert_ne!(other.logs, first.logs);
+    assert_ne!(other, first);
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use rustengan::middleware::Identity;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Echo { echo: String },
+    EchoOk { echo: String },
+}
+
+struct EchoNode;
+
+impl Node<(), Payload> for EchoNode {
+    fn from_init(_state: (), _init: Init, _tx: Sender<Event<Payload>>) -> anyhow::Result<Self> {
+        Ok(EchoNode)
+    }
+
+    fn step(&mut self, input: Event<Payload>, outbox: &mut Outbox) -> anyhow::Result<()> {
+        if let Event::Message(input) = input {
+            if let Payload::Echo { echo } = &input.body.payload {
+                let echo = echo.clone();
+                outbox.reply(&input, Payload::EchoOk { echo })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs an [`EchoNode`] over `transport` on a thread of its own.
+fn spawn_node<T>(transport: T) -> JoinHandle<anyhow::Result<()>>
+where
+    T: Transport + Send + 'static,
+{
+    thread::spawn(move || main_loop_on::<_, EchoNode, _, _, _, _>(transport, (), Identity))
+}
+
+fn send(to_node: &mut impl Write, body: Value) {
+    let msg = json!({ "src": "c1", "dest": "n1", "body": body });
+    writeln!(to_node, "{}", msg).expect("node reads its input");
+    to_node.flush().expect("node reads its input");
+}
+
+fn recv(from_node: &mut impl BufRead) -> Value {
+    let mut line = String::new();
+    from_node.read_line(&mut line).expect("node writes");
+    let msg: Value = serde_json::from_str(&line).expect("node writes JSON");
+    assert_eq!(msg["dest"], "c1", "{}", msg);
+    msg["body"].clone()
+}
+
+/// Takes the node through `init`, an echo and a type it does not know.
+fn talk(to_node: &mut impl Write, from_node: &mut impl BufRead) {
+    let init = json!({ "type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"] });
+    send(to_node, init);
+    let reply = recv(from_node);
+    assert_eq!(
+        (&reply["type"], &reply["in_reply_to"]),
+        (&json!("init_ok"), &json!(1))
+    );
+
+    send(
+        to_node,
+        json!({ "type": "echo", "msg_id": 2, "echo": "hi" }),
+    );
+    let reply = recv(from_node);
+    assert_eq!(reply["type"], "echo_ok");
+    assert_eq!(reply["echo"], "hi");
+    assert_eq!(reply["in_reply_to"], 2);
+
+    send(to_node, json!({ "type": "topology", "msg_id": 3 }));
+    let reply = recv(from_node);
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], 10);
+    assert_eq!(reply["in_reply_to"], 3);
+}
+
+#[test]
+fn runs_over_a_memory_pair() -> anyhow::Result<()> {
+    let (node_end, client_end) = MemoryTransport::pair();
+    let node = spawn_node(node_end);
+    let (mut from_node, mut to_node) = client_end.split()?;
+    talk(&mut to_node, &mut from_node);
+
+    // Dropping the writer is EOF to the node, which then returns.
+    drop(to_node);
+    node.join().expect("node thread")?;
+    let mut rest = String::new();
+    from_node.read_line(&mut rest)?;
+    assert_eq!(rest, "");
+    Ok(())
+}
+
+#[test]
+fn runs_over_tcp() -> anyhow::Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let node = spawn_node(TcpTransport::connect(listener.local_addr()?)?);
+    let (stream, _) = listener.accept()?;
+    let mut from_node = BufReader::new(stream.try_clone()?);
+    let mut to_node = &stream;
+    talk(&mut to_node, &mut from_node);
+
+    stream.shutdown(Shutdown::Write)?;
+    node.join().expect("node thread")?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_over_a_unix_socket() -> anyhow::Result<()> {
+    use std::os::unix::net::UnixListener;
+
+    let path = std::env::temp_dir().join(format!("rustengan-test-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    let node = spawn_node(UnixTransport::connect(&path)?);
+    let (stream, _) = listener.accept()?;
+    let mut from_node = BufReader::new(stream.try_clone()?);
+    let mut to_node = &stream;
+    talk(&mut to_node, &mut from_node);
+
+    stream.shutdown(Shutdown::Write)?;
+    node.join().expect("node thread")?;
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn replies_keep_the_order_of_their_requests() -> anyhow::Result<()> {
+    let (node_end, client_end) = MemoryTransport::pair();
+    let node = spawn_node(node_end);
+    let (mut from_node, mut to_node) = client_end.split()?;
+    let init = json!({ "type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"] });
+    send(&mut to_node, init);
+    assert_eq!(recv(&mut from_node)["type"], "init_ok");
+
+    // In one write, so that the node takes them on as one batch; the ones
+    // turned away must not overtake those before them.
+    let mut burst = Vec::new();
+    for body in [
+        json!({ "type": "echo", "msg_id": 2, "echo": "a" }),
+        json!({ "type": "topology", "msg_id": 3 }),
+        json!({ "type": "echo", "msg_id": 4, "echo": "b" }),
+        json!({ "type": "init", "msg_id": 5, "node_id": "n1", "node_ids": ["n1"] }),
+        json!({ "type": "echo", "msg_id": 6, "echo": "c" }),
+    ] {
+        writeln!(
+            burst,
+            "{}",
+            json!({ "src": "c1", "dest": "n1", "body": body })
+        )?;
+    }
+    to_node.write_all(&burst)?;
+    to_node.flush()?;
+
+    let replies: Vec<(Value, Value)> = (0..5)
+        .map(|_| recv(&mut from_node))
+        .map(|reply| (reply["in_reply_to"].clone(), reply["type"].clone()))
+        .collect();
+    assert_eq!(
+        replies,
+        [
+            (json!(2), json!("echo_ok")),
+            (json!(3), json!("error")),
+            (json!(4), json!("echo_ok")),
+            (json!(5), json!("error")),
+            (json!(6), json!("echo_ok")),
+        ]
+    );
+    drop(to_node);
+    node.join().expect("node thread")
+}